@@ -0,0 +1,101 @@
+use crate::io::ReadsFilesImpl;
+use crate::pipes::{PipeDefinition, PipeMap};
+use crate::utils::{hash_bytes, hash_str};
+use crate::yaml::{YamlValue, new_yaml_map, insert_value, to_json};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+//how a fingerprinted copy names its urls and where it leaves the manifest
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    //put in front of every url, e.g. `/static/`
+    pub url_prefix: String,
+    //where to write the whole manifest as json after the copy, if anywhere
+    pub manifest: Option<String>,
+}
+
+//every fingerprinted asset, from its plain path to its hashed url and the file it was copied from.
+//clones share the same manifest, so the one in the build's RenderOptions can be handed to the `asset`
+//pipe
+#[derive(Debug, Clone, Default)]
+pub struct AssetManifest {
+    assets: Arc<Mutex<BTreeMap<String, (String, String)>>>,
+}
+
+impl AssetManifest {
+    pub fn new() -> AssetManifest {
+        AssetManifest::default()
+    }
+
+    pub fn insert(&self, path: &str, url: &str, source: &str) {
+        self.assets.lock().unwrap().insert(path.to_owned(), (url.to_owned(), source.to_owned()));
+    }
+
+    pub fn get(&self, path: &str) -> Option<String> {
+        self.assets.lock().unwrap().get(path.trim_start_matches('/')).map(|(url, _)| url.to_owned())
+    }
+
+    //the file the asset was copied from
+    pub fn source(&self, path: &str) -> Option<String> {
+        self.assets.lock().unwrap().get(path.trim_start_matches('/')).map(|(_, source)| source.to_owned())
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //a build starts from nothing, so assets that have gone don't linger
+    pub fn clear(&self) {
+        self.assets.lock().unwrap().clear();
+    }
+
+    //changes whenever any asset's url does. 0 when there are none
+    pub fn hash(&self) -> u64 {
+        match self.is_empty() {
+            true => 0,
+            false => hash_str(&self.to_json()),
+        }
+    }
+
+    //sorted by path
+    pub fn to_json(&self) -> String {
+        let mut map = new_yaml_map();
+        for (path, (url, _)) in self.assets.lock().unwrap().iter() {
+            insert_value(&mut map, path, YamlValue::String(url.to_owned()));
+        }
+        to_json(&YamlValue::Hash(map))
+    }
+
+    //`{{ "css/app.css" | asset }}` gives the asset's fingerprinted url. only assets copied before the
+    //page is rendered are known, so the copy has to come first. the asset's source is read, so the page
+    //counts as depending on it and is rebuilt when its url changes
+    pub fn pipe(&self) -> PipeDefinition {
+        let manifest = self.clone();
+        PipeDefinition::Closure(Arc::new(move |input: &YamlValue, _pipes: &PipeMap, io: &mut ReadsFilesImpl| {
+            match &input["it"] {
+                YamlValue::String(path) => {
+                    let url = manifest.get(path).ok_or_else(|| format!("{} isn't a fingerprinted asset", path))?;
+                    if let Some(source) = manifest.source(path) {
+                        io.io.read_bytes(&source).map_err(|ee| format!("{:?}", ee))?;
+                    }
+                    Ok(YamlValue::String(url))
+                },
+                _ => Err("Pipe expects an asset path but it got something else".to_owned()),
+            }
+        }))
+    }
+}
+
+//`css/app.css` becomes `css/app.<hash>.css`, the hash being the first 8 hex digits of the contents'
+pub fn fingerprinted_name(path: &str, contents: &[u8]) -> String {
+    let hash = &format!("{:016x}", hash_bytes(contents))[..8];
+    let name_start = path.rfind('/').map_or(0, |ii| ii + 1);
+    match path[name_start..].rfind('.') {
+        Some(ii) if ii > 0 => format!("{}.{}{}", &path[..name_start + ii], hash, &path[name_start + ii..]),
+        _ => format!("{}.{}", path, hash),
+    }
+}
@@ -1,9 +1,17 @@
-use crate::yaml::{YamlMap, YamlValue, YamlFileError};
-use crate::template::{TemplateError, render, render_elements};
+use crate::yaml::{
+    YamlMap, YamlValue, YamlFileError, FrontMatterError, split_front_matter, new_yaml_map, insert_value,
+};
+use crate::template::{TemplateError, RenderOptions, render_named, render_elements};
 use crate::pipes::{PipeMap};
 use crate::io::{ReadsFiles, FileError};
-use crate::parsers::{parse_template_string};
-use crate::utils::{map_m, map_m_ref, fold_m_mut, map_m_mut};
+use crate::data::load_data_dir;
+use crate::copy::{CopyOptions, copy_matching};
+use crate::incremental::{BuildCache, TrackingFiles, hash_params};
+use crate::minify::MinifyOptions;
+use crate::transform::OutputTransforms;
+use crate::utils::hash_str;
+use std::sync::Mutex;
+use std::fmt;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum BuildError {
@@ -17,19 +25,47 @@ pub enum BuildError {
     BMInputNotSpecified(String),
     BMOutputNotSpecified(String),
     BMMappingParseError(String),
+    FrontMatterError(String, FrontMatterError),
+    LayoutCycle(String),
+    DataKeyConflict(String),
+    //a listed data file that isn't under the data directory. file, directory
+    DataFileOutsideDir(String, String),
+    BadGlob(String, String),
+    //everything that went wrong in a build that kept going
+    Many(Vec<EntryError>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParamsSource {
     File(String),
-    None
+    None,
+    //the action at this position in the list, rather than any one of its entries
+    Action(usize),
+}
+
+impl fmt::Display for ParamsSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamsSource::File(file) => write!(f, "{}", file),
+            ParamsSource::None => write!(f, "params"),
+            ParamsSource::Action(ii) => write!(f, "action {}", ii),
+        }
+    }
+}
+
+//an error along with where it came from. the index is the entry's position within its source
+#[derive(Debug, PartialEq, Eq)]
+pub struct EntryError {
+    pub source: ParamsSource,
+    pub index: Option<usize>,
+    pub error: BuildError,
 }
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct SourcedParams(YamlMap, ParamsSource, YamlMap); //params, source, mapping
+pub struct SourcedParams(YamlMap, ParamsSource, YamlMap, usize); //params, source, mapping, index
 
 #[derive(Debug, PartialEq, Eq)]
-pub struct SourcedParamsWithFiles(YamlMap, ParamsSource, String, String);
+pub struct SourcedParamsWithFiles(pub YamlMap, pub ParamsSource, pub String, pub String, pub usize); //params, source, input, output, index
 
 pub enum BuildAction {
     BuildPage {output: String, input: String, params: YamlMap},
@@ -37,7 +73,76 @@ pub enum BuildAction {
         default_params: YamlMap,
         on: Vec<BuildMultiplePages>,
     },
-    CopyFiles {to: String, from: String},
+    CopyFiles {to: String, from: String, options: CopyOptions},
+}
+
+//state shared by every action in a build
+#[derive(Debug, Default)]
+pub struct BuildContext {
+    //exposed to every render as `site`, underneath all other params
+    pub site: YamlMap,
+    pub render: RenderOptions,
+    //when set, pages whose inputs haven't changed since the cache was saved aren't rebuilt
+    pub cache: Option<Mutex<BuildCache>>,
+    //stop at the first error instead of building everything that can be built
+    pub fail_fast: bool,
+    //which pages are minified before they're written, by extension. off unless it's a production build
+    pub minify: MinifyOptions,
+    //run on each page before it's minified and written
+    pub transforms: OutputTransforms,
+}
+
+//the clone gets a copy of the cache rather than sharing it, so a dry run can't mark pages as built
+impl Clone for BuildContext {
+    fn clone(&self) -> BuildContext {
+        BuildContext{
+            site: self.site.clone(),
+            render: self.render.clone(),
+            cache: self.cache.as_ref().map(|cache| Mutex::new(cache.lock().unwrap().clone())),
+            fail_fast: self.fail_fast,
+            minify: self.minify,
+            transforms: self.transforms.clone(),
+        }
+    }
+}
+
+impl BuildContext {
+    //loads the data directory so its files are available as `site.data.<filename>`
+    pub fn with_data_dir(dir: &str, io: &mut impl ReadsFiles) -> Result<BuildContext, BuildError> {
+        let mut site = new_yaml_map();
+        insert_value(&mut site, "data", YamlValue::Hash(load_data_dir(dir, io)?));
+        Ok(BuildContext{site, ..BuildContext::default()})
+    }
+
+    pub fn production(self) -> BuildContext {
+        BuildContext{minify: MinifyOptions::production(), ..self}
+    }
+
+    //a build starts with no fingerprinted assets and nothing built or skipped yet
+    pub(crate) fn start_run(&self) {
+        self.render.assets.clear();
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().start_run();
+        }
+    }
+
+    //in fail fast mode the error is returned straight away, otherwise it's kept for the end
+    pub(crate) fn collect(&self, errors: &mut Vec<EntryError>, error: EntryError) -> Result<(), BuildError> {
+        if self.fail_fast {
+            Err(error.error)
+        } else {
+            errors.push(error);
+            Ok(())
+        }
+    }
+
+    fn base_params(&self) -> YamlMap {
+        let mut params = new_yaml_map();
+        if !self.site.is_empty() {
+            insert_value(&mut params, "site", YamlValue::Hash(self.site.to_owned()));
+        }
+        params
+    }
 }
 
 pub struct BuildMultiplePages {
@@ -48,80 +153,163 @@ pub struct BuildMultiplePages {
 
 impl BuildAction {
     pub fn run(&self, pipes: &PipeMap, io: &mut impl ReadsFiles) -> Result<(), BuildError> {
+        self.run_in(&BuildContext::default(), pipes, io)
+    }
+
+    pub fn run_in(
+        &self,
+        ctx: &BuildContext,
+        pipes: &PipeMap,
+        io: &mut impl ReadsFiles
+    ) -> Result<(), BuildError> {
         match self {
             BuildAction::BuildPage{output, input, params} => {
-                build_page(&input, &output, params, pipes, io)
+                build_page(ctx, input, output, &ctx.base_params(), params, pipes, io)
             },
             BuildAction::BuildMultiplePages{default_params, on} => {
-                let mut entries: Vec<SourcedParams> = vec![];
-                for mut source in map_m_ref(on, |xx| build_multiple_pages_files(xx, pipes, io))? {
-                    entries.append(&mut source);
-                };
-                let mapped = build_multiple_pages_map_params(default_params, entries, pipes, io)?;
-                build_multiple_pages_actually_build(mapped, pipes, io)
-            },
-            BuildAction::CopyFiles{to, from} => {
-                io.copy_files(from, to).map_err(|ee| BuildError::FileError(ee))
+                let mut errors: Vec<EntryError> = vec![];
+                let (defaults, mapped) = build_multiple_pages_entries(ctx, default_params, on, pipes, io, &mut errors)?;
+                build_multiple_pages_actually_build(ctx, &defaults, mapped, pipes, io, &mut errors)?;
+                many_or_ok(errors)
             },
+            BuildAction::CopyFiles{to, from, options} => copy_matching(ctx, from, to, options, pipes, io),
             _ => Ok(())
         }
     }
 }
 
+pub(crate) fn many_or_ok(errors: Vec<EntryError>) -> Result<(), BuildError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(BuildError::Many(errors))
+    }
+}
+
+//every page a BuildMultiplePages will write, along with the defaults they're all built with
+pub(crate) fn build_multiple_pages_entries(
+    ctx: &BuildContext,
+    default_params: &YamlMap,
+    on: &Vec<BuildMultiplePages>,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles,
+    errors: &mut Vec<EntryError>
+) -> Result<(YamlMap, Vec<SourcedParamsWithFiles>), BuildError> {
+    let mut entries: Vec<SourcedParams> = vec![];
+    for source in on {
+        entries.append(&mut build_multiple_pages_files(ctx, source, io, errors)?);
+    };
+    let mut defaults = ctx.base_params();
+    defaults.extend(default_params.to_owned());
+    let mapped = build_multiple_pages_map_params(ctx, &defaults, entries, pipes, io, errors)?;
+    Ok((defaults, mapped))
+}
+
 fn build_multiple_pages_files(
+    ctx: &BuildContext,
     on: &BuildMultiplePages,
-    pipes: &PipeMap,
-    io: &mut impl ReadsFiles
+    io: &mut impl ReadsFiles,
+    errors: &mut Vec<EntryError>
 ) -> Result<Vec<SourcedParams>, BuildError> {
     let mut entries: Vec<SourcedParams> = vec![];
-    map_m_ref(&on.files, |file| {
-        let contents: YamlValue = match io.read_yaml(&file) {
-            Ok(aa) => Ok(aa.to_owned()),
-            Err(ee) => Err(BuildError::YamlFileError(ee)),
-        }?;
+    for file in &on.files {
+        let source = ParamsSource::File(file.to_owned());
+        let contents: YamlValue = match io.read_yaml(file) {
+            Ok(aa) => aa.to_owned(),
+            Err(ee) => {
+                ctx.collect(errors, EntryError{source, index: None, error: BuildError::YamlFileError(ee)})?;
+                continue;
+            }
+        };
         let arr: Vec<YamlValue> = match contents {
-            YamlValue::Array(aa) => Ok(aa),
-            _ => Err(BuildError::BMFIsntArray(file.to_owned()))
-        }?;
-        map_m(arr, |aa| match aa {
-            YamlValue::Hash(hh) => Ok(entries.push(
-                    SourcedParams(hh.to_owned(), ParamsSource::File(file.to_owned()), on.mapping.to_owned())
-            )),
-            _ => Err(BuildError::BMFContainsNonMap(file.to_owned()))
-        })
-    })?;
-    for param in &on.params {
-        entries.push(SourcedParams(param.to_owned(), ParamsSource::None, on.mapping.to_owned()))
+            YamlValue::Array(aa) => aa,
+            //a stream holding just the one document
+            hh @ YamlValue::Hash(..) => vec![hh],
+            _ => {
+                let error = BuildError::BMFIsntArray(file.to_owned());
+                ctx.collect(errors, EntryError{source, index: None, error})?;
+                continue;
+            }
+        };
+        for (index, aa) in arr.into_iter().enumerate() {
+            match aa {
+                YamlValue::Hash(hh) => entries.push(SourcedParams(hh, source.clone(), on.mapping.to_owned(), index)),
+                _ => {
+                    let error = BuildError::BMFContainsNonMap(file.to_owned());
+                    ctx.collect(errors, EntryError{source: source.clone(), index: Some(index), error})?;
+                }
+            }
+        }
+    }
+    for (index, param) in on.params.iter().enumerate() {
+        entries.push(SourcedParams(param.to_owned(), ParamsSource::None, on.mapping.to_owned(), index))
     }
     Ok(entries)
 }
 
 fn build_multiple_pages_map_params(
+    ctx: &BuildContext,
     default_params: &YamlMap,
     values: Vec<SourcedParams>,
     pipes: &PipeMap,
-    io: &mut impl ReadsFiles
+    io: &mut impl ReadsFiles,
+    errors: &mut Vec<EntryError>
 ) -> Result<Vec<SourcedParamsWithFiles>, BuildError> {
-    map_m_mut(values, |ii| {
-        let mut params: YamlMap = default_params.to_owned();
-        params.extend(ii.0);
-        Ok(SourcedParamsWithFiles(params, ii.1, "".to_owned(), "".to_owned()))
-    })
+    let mut mapped = vec![];
+    for SourcedParams(entry, source, mut mapping, index) in values {
+        match map_entry_params(ctx, default_params, entry, &source, &mut mapping, pipes, io) {
+            Ok((params, input, output)) => mapped.push(SourcedParamsWithFiles(params, source, input, output, index)),
+            Err(error) => ctx.collect(errors, EntryError{source, index: Some(index), error})?,
+        }
+    }
+    Ok(mapped)
+}
+
+//the entry's params after mapping, and the input and output it names
+fn map_entry_params(
+    ctx: &BuildContext,
+    default_params: &YamlMap,
+    entry: YamlMap,
+    source: &ParamsSource,
+    mapping: &mut YamlMap,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles
+) -> Result<(YamlMap, String, String), BuildError> {
+    let mut merged: YamlMap = default_params.to_owned();
+    merged.extend(entry.clone());
+    apply_mapping(&mut merged, mapping, pipes, &ctx.render, io)?;
+    //keys produced by the mapping count as the entry's own, so they beat front matter
+    let mut params = entry;
+    for key in mapping.keys() {
+        if let Some(value) = merged.get(key) {
+            params.insert(key.to_owned(), value.to_owned());
+        }
+    }
+    let input = match merged.get(&YamlValue::String("input".to_owned())) {
+        Some(YamlValue::String(ss)) => ss.to_owned(),
+        _ => return Err(BuildError::BMInputNotSpecified(format!("{:?}", source))),
+    };
+    let output = match merged.get(&YamlValue::String("output".to_owned())) {
+        Some(YamlValue::String(ss)) => ss.to_owned(),
+        _ => return Err(BuildError::BMOutputNotSpecified(format!("{:?}", source))),
+    };
+    Ok((params, input, output))
 }
 
 pub fn apply_mapping<'a>(
     dest: &'a mut YamlMap,
     mapping: &'a mut YamlMap,
     pipes: &PipeMap,
+    options: &RenderOptions,
     io: &mut impl ReadsFiles
 ) -> Result<(), BuildError> {
     for (key, value) in mapping {
         match value {
             YamlValue::String(ss) => {
-                match parse_template_string(ss) {
-                    Err(ee) => return Err(BuildError::BMMappingParseError(ee.to_string())),
+                match options.templates.parse("", ss) {
+                    Err(ee) => return Err(BuildError::BMMappingParseError(ee)),
                     Ok(elements) => {
-                        let elements = render_elements(&elements, dest, pipes, io)
+                        let elements = render_elements(&elements, dest, pipes, options, io)
                             .map_err(|xx| BuildError::TemplateError(xx))?;
                         dest.insert(key.to_owned(), YamlValue::String(elements));
                     }
@@ -133,30 +321,208 @@ pub fn apply_mapping<'a>(
     Ok(())
 }
 
+//pages that fail don't stop the rest from being written unless we're failing fast
 fn build_multiple_pages_actually_build(
+    ctx: &BuildContext,
+    default_params: &YamlMap,
     values: Vec<SourcedParamsWithFiles>,
     pipes: &PipeMap,
-    io: &mut impl ReadsFiles
+    io: &mut impl ReadsFiles,
+    errors: &mut Vec<EntryError>
 ) -> Result<(), BuildError> {
-    fold_m_mut((), values, |_, ii: SourcedParamsWithFiles| {
-        build_page(&ii.2, &ii.3, &ii.0, pipes, io)
-            .map_err(|ee| BuildError::Sourced(Box::new(ee)))
-    })?;
+    for ii in values {
+        if let Err(ee) = build_page(ctx, &ii.2, &ii.3, default_params, &ii.0, pipes, io) {
+            collect_page_error(ctx, errors, ii.1, ii.4, ee)?;
+        }
+    }
     Ok(())
 }
 
-fn build_page(
+pub(crate) fn collect_page_error(
+    ctx: &BuildContext,
+    errors: &mut Vec<EntryError>,
+    source: ParamsSource,
+    index: usize,
+    error: BuildError
+) -> Result<(), BuildError> {
+    if ctx.fail_fast {
+        return Err(BuildError::Sourced(Box::new(error)));
+    }
+    ctx.collect(errors, EntryError{source, index: Some(index), error})
+}
+
+//runs every action in order. unless failing fast, an action that fails doesn't stop the ones after
+//it and a summary of every error is printed at the end
+pub fn run_actions(
+    actions: &[BuildAction],
+    ctx: &BuildContext,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles
+) -> Result<(), BuildError> {
+    ctx.start_run();
+    let mut errors: Vec<EntryError> = vec![];
+    for (ii, action) in actions.iter().enumerate() {
+        match action.run_in(ctx, pipes, io) {
+            Ok(()) => (),
+            Err(error) if ctx.fail_fast => return Err(error),
+            Err(error) => push_action_error(&mut errors, ii, error),
+        }
+    }
+    report_errors(errors)
+}
+
+pub(crate) fn push_action_error(errors: &mut Vec<EntryError>, action: usize, error: BuildError) {
+    match error {
+        BuildError::Many(mut many) => errors.append(&mut many),
+        error => errors.push(EntryError{source: ParamsSource::Action(action), index: None, error}),
+    }
+}
+
+pub(crate) fn report_errors(errors: Vec<EntryError>) -> Result<(), BuildError> {
+    if !errors.is_empty() {
+        eprint!("{}", error_summary(&errors));
+    }
+    many_or_ok(errors)
+}
+
+//the errors grouped by where they came from, in the order the sources were first seen
+pub fn error_summary(errors: &[EntryError]) -> String {
+    let mut sources: Vec<&ParamsSource> = vec![];
+    for ee in errors {
+        if !sources.contains(&&ee.source) {
+            sources.push(&ee.source);
+        }
+    }
+    let mut summary = format!("{} error{}\n", errors.len(), if errors.len() == 1 {""} else {"s"});
+    for source in sources {
+        summary.push_str(&format!("  {}:\n", source));
+        for ee in errors.iter().filter(|ee| &ee.source == source) {
+            match ee.index {
+                Some(ii) => summary.push_str(&format!("    entry {}: {:?}\n", ii, ee.error)),
+                None => summary.push_str(&format!("    {:?}\n", ee.error)),
+            }
+        }
+    }
+    summary
+}
+
+//reads a template and splits off its front matter
+fn read_with_front_matter(
+    filename: &str,
+    io: &mut impl ReadsFiles
+) -> Result<(YamlMap, String), BuildError> {
+    let contents = io.read(filename).map_err(BuildError::FileError)?;
+    let (front, body) = split_front_matter(contents)
+        .map_err(|ee| BuildError::FrontMatterError(filename.to_owned(), ee))?;
+    Ok((front, body.to_owned()))
+}
+
+//skips the page when the build cache says nothing it depends on has changed
+pub(crate) fn build_page(
+    ctx: &BuildContext,
     input: &str,
     output: &str,
+    defaults: &YamlMap,
     params: &YamlMap,
     pipes: &PipeMap,
     io: &mut impl ReadsFiles
 ) -> Result<(), BuildError> {
-    let contents = match io.read(input) {
-        Ok(ss) => Ok(ss.to_owned()),
-        Err(ee) => Err(BuildError::FileError(ee)),
-    }?;
-    let rendered = render(&contents, params, pipes, io)
-        .map_err(|xx| BuildError::TemplateError(xx))?;
-    io.write(output, &rendered).map_err(|xx| BuildError::FileError(xx))
+    let cache = match &ctx.cache {
+        None => {
+            let (rendered, layered) = render_page(ctx, input, defaults, params, pipes, io)?;
+            return write_page(ctx, output, &rendered, &layered, io);
+        },
+        Some(cache) => cache,
+    };
+    let mut all_params = defaults.to_owned();
+    all_params.extend(params.to_owned());
+    let params_hash = page_hash(ctx, &all_params);
+    if cache.lock().unwrap().is_fresh(output, params_hash, io) {
+        return Ok(());
+    }
+    let mut tracking = TrackingFiles::new(io);
+    let (rendered, layered) = render_page(ctx, input, defaults, params, pipes, &mut tracking)?;
+    let reads = tracking.reads();
+    write_page(ctx, output, &rendered, &layered, io)?;
+    cache.lock().unwrap().record(output, params_hash, &reads, io);
+    Ok(())
+}
+
+//minified and plain builds of the same page aren't interchangeable, and neither are ones through
+//different transforms. a page that links to fingerprinted assets has to be rebuilt when any of their
+//urls change
+fn page_hash(ctx: &BuildContext, params: &YamlMap) -> u64 {
+    let mut hash = hash_params(params) ^ ctx.render.assets.hash() ^ ctx.transforms.hash();
+    if !ctx.minify.is_off() {
+        hash ^= hash_str(&format!("{:?}", ctx.minify));
+    }
+    hash
+}
+
+//through the transforms, which see the page's params as its templates did and may veto it, then minified if the output's extension calls for it
+fn write_page(
+    ctx: &BuildContext,
+    output: &str,
+    rendered: &str,
+    params: &YamlMap,
+    io: &mut impl ReadsFiles
+) -> Result<(), BuildError> {
+    let transformed = match ctx.transforms.is_empty() {
+        true => None,
+        false => match ctx.transforms.apply(output, rendered, params) {
+            Some(transformed) => Some(transformed),
+            None => return Ok(()),
+        },
+    };
+    let rendered = transformed.as_deref().unwrap_or(rendered);
+    match ctx.minify.minify(output, rendered) {
+        Some(minified) => io.write(output, &minified),
+        None => io.write(output, rendered),
+    }.map_err(BuildError::FileError)
+}
+
+//params are layered lowest to highest as: defaults (which include the site data), the input's
+//front matter, then the page's own params. if the result has a `layout` the rendered page is passed
+//to it as `content`, with the layout's front matter slotting in underneath the page's. the params
+//the last template saw come back with the page, less the `content`
+fn render_page(
+    ctx: &BuildContext,
+    input: &str,
+    defaults: &YamlMap,
+    params: &YamlMap,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles
+) -> Result<(String, YamlMap), BuildError> {
+    let input_file = ctx.render.sources.resolve_page(input, io);
+    let (front, body) = read_with_front_matter(&input_file, io)?;
+    let mut layered: YamlMap = front;
+    layered.extend(params.to_owned());
+    let mut current: YamlMap = defaults.to_owned();
+    current.extend(layered.clone());
+    let mut rendered = render_named(&input_file, &body, &current, pipes, &ctx.render, io)
+        .map_err(BuildError::TemplateError)?;
+    let mut seen: Vec<String> = vec![input.to_owned()];
+    while let Some(YamlValue::String(layout)) = current.get(&YamlValue::String("layout".to_owned())) {
+        let layout = layout.to_owned();
+        if seen.contains(&layout) {
+            return Err(BuildError::LayoutCycle(layout));
+        }
+        let layout_file = ctx.render.sources.resolve(&layout, false, io);
+        let (layout_front, layout_body) = read_with_front_matter(&layout_file, io)?;
+        layered.remove(&YamlValue::String("layout".to_owned()));
+        let mut below = layout_front;
+        below.extend(layered);
+        layered = below;
+        current = defaults.to_owned();
+        current.remove(&YamlValue::String("layout".to_owned()));
+        current.extend(layered.clone());
+        insert_value(&mut current, "content", YamlValue::String(rendered));
+        rendered = render_named(&layout_file, &layout_body, &current, pipes, &ctx.render, io)
+            .map_err(|xx| BuildError::TemplateErrorForFile(layout.to_owned(), xx))?;
+        seen.push(layout);
+    }
+    if seen.len() > 1 {
+        current.remove(&YamlValue::String("content".to_owned()));
+    }
+    Ok((rendered, current))
 }
@@ -0,0 +1,156 @@
+use crate::assets::{Fingerprint, fingerprinted_name};
+use crate::build::{BuildContext, BuildError};
+use crate::io::{ReadsFiles, CopyMode};
+use crate::pipes::PipeMap;
+use crate::template::render_with;
+use crate::utils::clean_path;
+use crate::yaml::{YamlMap, YamlValue, new_yaml_map, insert_value};
+use glob::{MatchOptions, Pattern};
+
+//how a CopyFiles picks and places its files. the default copies everything as it is
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CopyOptions {
+    //globs matched against each file's path under `from`, or just its name if the glob has no slash.
+    //matching a directory counts for everything in it. no includes means every file. `{a,b}`
+    //alternatives are allowed
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    //template for each file's path under `to`, given its `path`, `dir`, `name`, `stem` and `ext`.
+    //`{{name}}` flattens everything into one directory
+    pub rename: Option<String>,
+    pub mode: CopyMode,
+    //gives every file a content hashed name and records it in the build's asset manifest
+    pub fingerprint: Option<Fingerprint>,
+}
+
+//glob has no `{a,b}`, so those become one pattern per alternative
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(ii) => ii,
+        None => return vec![pattern.to_owned()],
+    };
+    let close = match pattern[open..].find('}') {
+        Some(ii) => open + ii,
+        None => return vec![pattern.to_owned()],
+    };
+    let (before, after) = (&pattern[..open], &pattern[close + 1..]);
+    pattern[open + 1..close].split(',')
+        .flat_map(|alternative| expand_braces(&format!("{}{}{}", before, alternative, after)))
+        .collect()
+}
+
+pub(crate) struct Globs(Vec<(Pattern, bool)>); //pattern, whether it's matched against the whole path
+
+impl Globs {
+    pub(crate) fn new(globs: &[String]) -> Result<Globs, BuildError> {
+        let mut patterns = vec![];
+        for glob in globs.iter().flat_map(|gg| expand_braces(gg)) {
+            let pattern = Pattern::new(&glob).map_err(|ee| BuildError::BadGlob(glob.to_owned(), ee.to_string()))?;
+            patterns.push((pattern, glob.contains('/')));
+        }
+        Ok(Globs(patterns))
+    }
+
+    //a directory that matches takes everything under it along
+    pub(crate) fn matches(&self, relative: &str) -> bool {
+        let options = MatchOptions{require_literal_separator: true, ..MatchOptions::new()};
+        let mut path = relative;
+        loop {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            if self.0.iter().any(|(pattern, whole)| pattern.matches_with(if *whole {path} else {name}, options)) {
+                return true;
+            }
+            match path.rfind('/') {
+                Some(ii) => path = &path[..ii],
+                None => return false,
+            }
+        }
+    }
+}
+
+fn rename_params(relative: &str) -> YamlMap {
+    let (dir, name) = match relative.rfind('/') {
+        Some(ii) => (&relative[..ii], &relative[ii + 1..]),
+        None => ("", relative),
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(ii) if ii > 0 => (&name[..ii], &name[ii + 1..]),
+        _ => (name, ""),
+    };
+    let mut params = new_yaml_map();
+    for (key, value) in [("path", relative), ("dir", dir), ("name", name), ("stem", stem), ("ext", ext)] {
+        insert_value(&mut params, key, YamlValue::String(value.to_owned()));
+    }
+    params
+}
+
+//copies `from` to `to` following the options. a directory is walked file by file so each one can be
+//filtered and renamed; a single file is matched on its name and lands at `to` unless it's renamed
+pub fn copy_matching(
+    ctx: &BuildContext,
+    from: &str,
+    to: &str,
+    options: &CopyOptions,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles
+) -> Result<(), BuildError> {
+    if *options == CopyOptions::default() {
+        return io.copy_files(from, to).map_err(BuildError::FileError);
+    }
+    let include = Globs::new(&options.include)?;
+    let exclude = Globs::new(&options.exclude)?;
+    let root = clean_path(from);
+    let (files, single) = match io.list_files(from) {
+        Ok(files) => (files, false),
+        Err(_) => (vec![from.to_owned()], true),
+    };
+    for file in files {
+        let normalised = clean_path(&file);
+        let relative = if single {
+            normalised.rsplit('/').next().unwrap_or(&normalised)
+        } else {
+            normalised.strip_prefix(root.trim_end_matches('/')).unwrap_or(&normalised).trim_start_matches('/')
+        };
+        if (!options.include.is_empty() && !include.matches(relative)) || exclude.matches(relative) {
+            continue;
+        }
+        let destination = match &options.rename {
+            Some(template) => {
+                let renamed = render_with(template, &rename_params(relative), pipes, &ctx.render, io)
+                    .map_err(BuildError::TemplateError)?;
+                format!("{}/{}", to.trim_end_matches('/'), renamed.trim_start_matches('/'))
+            },
+            None if single => to.to_owned(),
+            None => format!("{}/{}", to.trim_end_matches('/'), relative),
+        };
+        let destination = match &options.fingerprint {
+            Some(fingerprint) => fingerprint_file(ctx, &file, &destination, to, single, fingerprint, io)?,
+            None => destination,
+        };
+        io.copy_file(&file, &destination, options.mode).map_err(BuildError::FileError)?;
+    }
+    if let Some(manifest) = options.fingerprint.as_ref().and_then(|ff| ff.manifest.as_ref()) {
+        io.write(manifest, &ctx.render.assets.to_json()).map_err(BuildError::FileError)?;
+    }
+    Ok(())
+}
+
+//the hashed destination, having recorded it under its path below `to` (or its name, for a single file)
+fn fingerprint_file(
+    ctx: &BuildContext,
+    file: &str,
+    destination: &str,
+    to: &str,
+    single: bool,
+    fingerprint: &Fingerprint,
+    io: &mut impl ReadsFiles
+) -> Result<String, BuildError> {
+    let hashed = fingerprinted_name(destination, io.read_bytes(file).map_err(BuildError::FileError)?);
+    let below = |path: &str| if single {
+        path.rsplit('/').next().unwrap_or(path).to_owned()
+    } else {
+        path.strip_prefix(to.trim_end_matches('/')).unwrap_or(path).trim_start_matches('/').to_owned()
+    };
+    ctx.render.assets.insert(&below(destination), &format!("{}{}", fingerprint.url_prefix, below(&hashed)), file);
+    Ok(hashed)
+}
@@ -0,0 +1,71 @@
+use crate::yaml::{YamlMap, YamlValue, DataFormat, new_yaml_map};
+use crate::io::ReadsFiles;
+use crate::build::BuildError;
+use crate::utils::clean_path;
+use std::collections::HashSet;
+
+//loads every data file under the directory into one map keyed by file name without its extension.
+//files in subdirectories end up in nested maps, so data/nav/main.yaml is at nav.main
+pub fn load_data_dir(dir: &str, io: &mut impl ReadsFiles) -> Result<YamlMap, BuildError> {
+    //listings don't always name files the way the directory was given, e.g. `./data` lists `data/a.yaml`
+    let root = clean_path(dir);
+    let mut data = new_yaml_map();
+    let mut stems = HashSet::new();
+    for filename in io.list_files(dir).map_err(BuildError::FileError)? {
+        let cleaned = clean_path(&filename);
+        let relative = match cleaned.strip_prefix(&root) {
+            Some(rest) if root.is_empty() || root.ends_with('/') || rest.starts_with('/') => rest.trim_start_matches('/'),
+            _ => return Err(BuildError::DataFileOutsideDir(filename.to_owned(), dir.to_owned())),
+        };
+        let (stem, extension) = match relative.rsplit_once('.') {
+            Some(split) => split,
+            None => continue,
+        };
+        let format = match DataFormat::from_extension(extension) {
+            Some(ff) => ff,
+            None => continue,
+        };
+        let value = io.read_data(&filename, format)
+            .map_err(BuildError::YamlFileError)?
+            .to_owned();
+        //two files with the same stem, e.g. a.yaml and a.json, would claim the same key
+        if !stems.insert(stem.to_owned()) {
+            return Err(BuildError::DataKeyConflict(filename.to_owned()));
+        }
+        let path: Vec<&str> = stem.split('/').collect();
+        insert_data_path(&mut data, &path, value, &filename)?;
+    }
+    Ok(data)
+}
+
+fn insert_data_path(
+    map: &mut YamlMap,
+    path: &[&str],
+    value: YamlValue,
+    filename: &str
+) -> Result<(), BuildError> {
+    let key = YamlValue::String(path[0].to_owned());
+    if path.len() == 1 {
+        match map.get_mut(&key) {
+            //a directory of the same name got there first, let the file's keys sit alongside it
+            Some(YamlValue::Hash(existing)) => match value {
+                YamlValue::Hash(hh) => {
+                    existing.extend(hh);
+                    Ok(())
+                },
+                _ => Err(BuildError::DataKeyConflict(filename.to_owned())),
+            },
+            Some(_) => Err(BuildError::DataKeyConflict(filename.to_owned())),
+            None => {
+                map.insert(key, value);
+                Ok(())
+            }
+        }
+    } else {
+        let entry = map.entry(key).or_insert_with(|| YamlValue::Hash(new_yaml_map()));
+        match entry {
+            YamlValue::Hash(inner) => insert_data_path(inner, &path[1..], value, filename),
+            _ => Err(BuildError::DataKeyConflict(filename.to_owned())),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::yaml::{YamlValue, YamlMap, YamlFileError, DataFormat, new_yaml_map, load_yaml, insert_value, to_json};
+use crate::io::{ReadsFiles, FileError, CopyMode};
+use crate::utils::{hash_bytes, hash_str};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use yaml_rust2::emitter::YamlEmitter;
+
+//wraps a ReadsFiles and remembers every file read through it, so we know what an output depends on.
+//the source of a copy counts as read, a directory covering everything under it
+pub struct TrackingFiles<'a, R: ReadsFiles> {
+    inner: &'a mut R,
+    reads: RefCell<Vec<String>>,
+}
+
+impl<'a, R: ReadsFiles> TrackingFiles<'a, R> {
+    pub fn new(inner: &'a mut R) -> TrackingFiles<'a, R> {
+        TrackingFiles{inner, reads: RefCell::new(vec![])}
+    }
+
+    pub fn reads(&self) -> Vec<String> {
+        self.reads.borrow().clone()
+    }
+
+    fn track(&self, filename: &str) {
+        let mut reads = self.reads.borrow_mut();
+        if !reads.iter().any(|ii| ii == filename) {
+            reads.push(filename.to_owned());
+        }
+    }
+}
+
+impl<'a, R: ReadsFiles> ReadsFiles for TrackingFiles<'a, R> {
+    fn read(&mut self, filename: &str) -> Result<&str, FileError> {
+        self.track(filename);
+        self.inner.read(filename)
+    }
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        self.track(filename);
+        self.inner.read_bytes(filename)
+    }
+    fn exists(&mut self, filename: &str) -> bool {
+        self.track(filename);
+        self.inner.exists(filename)
+    }
+    fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
+        self.inner.write(filename, contents)
+    }
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        self.inner.write_bytes(filename, contents)
+    }
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
+        self.track(filename);
+        self.inner.read_data(filename, format)
+    }
+    fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.track(from);
+        self.inner.copy_files(from, to)
+    }
+    fn copy_file(&self, from: &str, to: &str, mode: CopyMode) -> Result<(), FileError> {
+        self.track(from);
+        self.inner.copy_file(from, to, mode)
+    }
+    //a listing depends on everything under the directory
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        self.track(dir);
+        self.inner.list_files(dir)
+    }
+    fn remove(&mut self, filename: &str) -> Result<(), FileError> {
+        self.inner.remove(filename)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CacheEntry {
+    params: u64,
+    inputs: Vec<(String, u64)>,
+}
+
+//what every output was last built from. an output is rebuilt when its params or the contents of any
+//file it read have changed since. deleting the cache file forces a full rebuild
+#[derive(Debug, Default, Clone)]
+pub struct BuildCache {
+    entries: HashMap<String, CacheEntry>,
+    pub built: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+pub fn hash_params(params: &YamlMap) -> u64 {
+    hash_str(&to_json(&YamlValue::Hash(params.to_owned())))
+}
+
+//stands in for a file that wasn't there, so one turning up later (like a site override of a theme
+//file that was only probed for) counts as a change
+const MISSING: u64 = 0;
+
+//a file's bytes, or for a directory its listing, or MISSING
+fn hash_file(filename: &str, io: &mut impl ReadsFiles) -> u64 {
+    if let Ok(bytes) = io.read_bytes(filename) {
+        return hash_bytes(bytes);
+    }
+    match io.list_files(filename) {
+        Ok(files) => hash_str(&format!("dir\n{}", files.join("\n"))),
+        Err(_) => MISSING,
+    }
+}
+
+fn parse_hash(value: &YamlValue) -> Option<u64> {
+    value.as_str().and_then(|ss| u64::from_str_radix(ss, 16).ok())
+}
+
+impl BuildCache {
+    pub fn new() -> BuildCache {
+        BuildCache::default()
+    }
+
+    //a missing cache file just means everything gets built
+    pub fn load(filename: &str, io: &mut impl ReadsFiles) -> Result<BuildCache, YamlFileError> {
+        let contents = match io.read(filename) {
+            Ok(ss) => ss.to_owned(),
+            Err(FileError::FileNotFound(..)) => return Ok(BuildCache::new()),
+            Err(ee) => return Err(YamlFileError::File(ee)),
+        };
+        let mut cache = BuildCache::new();
+        let loaded = match load_yaml(&contents) {
+            Err(YamlFileError::Empty) => return Ok(cache),
+            other => other?,
+        };
+        if let YamlValue::Hash(outputs) = loaded {
+            for (output, entry) in outputs {
+                let params = parse_hash(&entry["params"]);
+                let inputs = entry["inputs"].as_hash().map(|hh| hh.iter()
+                    .filter_map(|(kk, vv)| Some((kk.as_str()?.to_owned(), parse_hash(vv)?)))
+                    .collect::<Vec<(String, u64)>>()
+                );
+                if let (Some(output), Some(params), Some(inputs)) = (output.as_str(), params, inputs) {
+                    cache.entries.insert(output.to_owned(), CacheEntry{params, inputs});
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    pub fn save(&self, filename: &str, io: &mut impl ReadsFiles) -> Result<(), FileError> {
+        let mut outputs: Vec<&String> = self.entries.keys().collect();
+        outputs.sort();
+        let mut doc = new_yaml_map();
+        for output in outputs {
+            let entry = &self.entries[output];
+            let mut inputs = new_yaml_map();
+            for (input, hash) in &entry.inputs {
+                insert_value(&mut inputs, input, YamlValue::String(format!("{:016x}", hash)));
+            }
+            let mut value = new_yaml_map();
+            insert_value(&mut value, "params", YamlValue::String(format!("{:016x}", entry.params)));
+            insert_value(&mut value, "inputs", YamlValue::Hash(inputs));
+            insert_value(&mut doc, output, YamlValue::Hash(value));
+        }
+        let mut out = String::new();
+        YamlEmitter::new(&mut out).dump(&YamlValue::Hash(doc))
+            .map_err(|_| FileError::FileCantBeWritten(filename.to_owned()))?;
+        out.push('\n');
+        io.write(filename, &out)
+    }
+
+    //whether the output can be left as it is, noting it as skipped if so
+    //an output that's gone missing, say because the output dir was wiped, is never fresh
+    pub fn is_fresh(&mut self, output: &str, params: u64, io: &mut impl ReadsFiles) -> bool {
+        let fresh = match self.entries.get(output) {
+            None => false,
+            Some(_) if !io.exists(output) => false,
+            Some(entry) => entry.params == params && entry.inputs.iter()
+                .all(|(input, hash)| hash_file(input, io) == *hash),
+        };
+        if fresh {
+            self.skipped.push(output.to_owned());
+        }
+        fresh
+    }
+
+    //forgets what earlier runs built and skipped
+    pub fn start_run(&mut self) {
+        self.built.clear();
+        self.skipped.clear();
+    }
+
+    pub fn record(&mut self, output: &str, params: u64, reads: &[String], io: &mut impl ReadsFiles) {
+        let inputs = reads.iter().map(|input| (input.to_owned(), hash_file(input, io))).collect();
+        self.entries.insert(output.to_owned(), CacheEntry{params, inputs});
+        self.built.push(output.to_owned());
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!("built {}, skipped {} unchanged", self.built.len(), self.skipped.len());
+        if !self.skipped.is_empty() {
+            summary.push_str(&format!(": {}", self.skipped.join(", ")));
+        }
+        summary
+    }
+}
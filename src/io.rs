@@ -1,12 +1,14 @@
-use crate::yaml::{YamlValue, YamlFileError, load_yaml};
-use crate::utils::{map_m};
+use crate::yaml::{YamlValue, YamlFileError, DataFormat, load_data};
+use crate::utils::{map_m, clean_path};
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::fs;
+use std::sync::{Arc, Mutex};
 use std::fmt;
+use std::time::SystemTime;
 use std::io;
-use glob::{glob, Paths, GlobError};
+use glob::glob;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum FileError {
@@ -15,18 +17,67 @@ pub enum FileError {
     FileCantBeWritten(String),
     FilesCantBeCopied(String),
     CantCopyDirIntoFile(String, String),
+    FileCantBeRemoved(String),
+    //the path leads outside every directory the sandbox allows
+    OutsideSandbox(String),
 }
 
 pub trait ReadsFiles {
     fn read(&mut self, filename: &str) -> Result<&str, FileError>;
     fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError>;
-    fn read_yaml(&mut self, filename: &str) -> Result<&YamlValue, YamlFileError>;
-    fn copy_files(&self, to: &str, from: &str) -> Result<(), FileError>;
+    //the raw contents, for files that needn't be text. the defaults only manage text
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        self.read(filename).map(|contents| contents.as_bytes())
+    }
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        let text = std::str::from_utf8(contents).map_err(|_| FileError::FileCantBeWritten(filename.to_owned()))?;
+        self.write(filename, text)
+    }
+    //whether there's a file there, without loading it. the default has to read it
+    fn exists(&mut self, filename: &str) -> bool {
+        self.read_bytes(filename).is_ok()
+    }
+    //loads a data file in the given format
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError>;
+    //loads a data file, picking the format from its extension
+    fn read_yaml(&mut self, filename: &str) -> Result<&YamlValue, YamlFileError> {
+        self.read_data(filename, DataFormat::from_filename(filename))
+    }
+    //copies a file, or a directory and everything in it
+    fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError>;
+    //puts a single file at `to`, creating the directories it needs
+    fn copy_file(&self, from: &str, to: &str, mode: CopyMode) -> Result<(), FileError>;
+    //every file under the directory, recursively, in sorted order
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError>;
+    fn remove(&mut self, filename: &str) -> Result<(), FileError>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMode {
+    #[default]
+    Copy,
+    Symlink,
+    Hardlink,
+}
+
+//a ReadsFiles that can hand out handles to the same files for use on other threads
+pub trait ForksFiles: ReadsFiles + Send {
+    fn fork(&self) -> Self;
 }
 
 //thing we need because we can't use 'impl ReadsFiles' in PipeDefinition's type definition
 pub struct ReadsFilesImpl<'a> {
-    pub read: &'a dyn FnMut(&'a str) -> Result<String, FileError>
+    pub io: &'a mut dyn ReadsFiles,
+}
+
+impl<'a> ReadsFilesImpl<'a> {
+    pub fn read(&mut self, filename: &str) -> Result<String, FileError> {
+        self.io.read(filename).map(|contents| contents.to_owned())
+    }
+
+    pub fn read_bytes(&mut self, filename: &str) -> Result<Vec<u8>, FileError> {
+        self.io.read_bytes(filename).map(|contents| contents.to_vec())
+    }
 }
 impl<'a> fmt::Display for ReadsFilesImpl<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -34,15 +85,287 @@ impl<'a> fmt::Display for ReadsFilesImpl<'a> {
     }
 }
 
+//how many writes actually went to disk, and how many were skipped because nothing had changed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStats {
+    pub written: usize,
+    pub unchanged: usize,
+}
+
+impl fmt::Display for WriteStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "wrote {}, {} unchanged", self.written, self.unchanged)
+    }
+}
+
+//the directories a FileCache may touch. paths are canonicalized before they're checked, so neither
+//`..` nor symlinks get out. the roots can be read and written, the allowed directories only read
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    roots: Vec<PathBuf>,
+    allowed: Vec<PathBuf>,
+}
+
+//the path with every `..` and symlink resolved. a path that doesn't exist yet is resolved as far as
+//it does, and anything after that can't step back up
+fn canonical(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = fs::canonicalize(path) {
+        return Some(path);
+    }
+    let mut rest: Vec<&std::ffi::OsStr> = vec![];
+    let mut existing = path;
+    loop {
+        match existing.components().next_back()? {
+            Component::Normal(part) => rest.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+        existing = existing.parent()?;
+        let base = if existing.as_os_str().is_empty() {Path::new(".")} else {existing};
+        if let Ok(mut resolved) = fs::canonicalize(base) {
+            resolved.extend(rest.iter().rev());
+            return Some(resolved);
+        }
+    }
+}
+
+fn canonical_dir(dir: &str) -> Result<PathBuf, FileError> {
+    fs::canonicalize(dir).map_err(|_| FileError::FileNotFound(dir.to_owned()))
+}
+
+impl Sandbox {
+    pub fn new(roots: &[&str]) -> Result<Sandbox, FileError> {
+        let roots = roots.iter().map(|rr| canonical_dir(rr)).collect::<Result<Vec<PathBuf>, FileError>>()?;
+        Ok(Sandbox{roots, allowed: vec![]})
+    }
+
+    //lets files under the directory be read too
+    pub fn allow(mut self, dir: &str) -> Result<Sandbox, FileError> {
+        self.allowed.push(canonical_dir(dir)?);
+        Ok(self)
+    }
+
+    fn check(&self, filename: &str, writing: bool) -> Result<(), FileError> {
+        let inside = match canonical(Path::new(filename)) {
+            Some(path) => {
+                self.roots.iter().any(|rr| path.starts_with(rr))
+                    || (!writing && self.allowed.iter().any(|aa| path.starts_with(aa)))
+            },
+            None => false,
+        };
+        if inside {
+            Ok(())
+        } else {
+            Err(FileError::OutsideSandbox(filename.to_owned()))
+        }
+    }
+
+    pub fn check_read(&self, filename: &str) -> Result<(), FileError> {
+        self.check(filename, false)
+    }
+
+    pub fn check_write(&self, filename: &str) -> Result<(), FileError> {
+        self.check(filename, true)
+    }
+}
+
+//how reads went. a reload is a cached file that had changed on disk, and evictions make room for
+//newer files when there's a budget
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub reloads: usize,
+    pub evictions: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} hits, {} misses, {} reloaded, {} evicted", self.hits, self.misses, self.reloads, self.evictions)
+    }
+}
+
+//files are kept as text when they are text, so reading them as text needn't check them every time
+enum Contents {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Contents {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Contents::Text(text) => text.as_bytes(),
+            Contents::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+struct CachedFile {
+    contents: Contents,
+    //modification time and size when it was read
+    stamp: Option<(SystemTime, u64)>,
+    last_used: u64,
+}
+
+fn stamp(filename: &str) -> Option<(SystemTime, u64)> {
+    fs::metadata(filename).ok().map(|meta| (meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len()))
+}
+
 pub struct FileCache {
-    files: HashMap<String, String>,
-    yamls: HashMap<String, YamlValue>,
+    files: HashMap<String, CachedFile>,
+    yamls: HashMap<(String, DataFormat), YamlValue>,
+    pub stats: WriteStats,
+    pub reads: CacheStats,
+    //no sandbox means any file can be touched
+    sandbox: Option<Sandbox>,
+    //when set, every read checks the file's modification time and size and rereads it if they've moved
+    check_changes: bool,
+    //the most bytes of file contents to keep. the least recently read files go first
+    budget: Option<usize>,
+    cached_bytes: usize,
+    clock: u64,
 }
 
-fn read_file(filename: &str) -> Result<String, FileError> {
+impl FileCache {
+    pub fn new() -> FileCache {
+        FileCache{
+            files: HashMap::new(),
+            yamls: HashMap::new(),
+            stats: WriteStats::default(),
+            reads: CacheStats::default(),
+            sandbox: None,
+            check_changes: false,
+            budget: None,
+            cached_bytes: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn with_budget(self, bytes: usize) -> FileCache {
+        FileCache{budget: Some(bytes), ..self}
+    }
+
+    pub fn checking_changes(self) -> FileCache {
+        FileCache{check_changes: true, ..self}
+    }
+
+    pub fn cached_bytes(&self) -> usize {
+        self.cached_bytes
+    }
+
+    pub fn sandboxed(sandbox: Sandbox) -> FileCache {
+        FileCache{sandbox: Some(sandbox), ..FileCache::new()}
+    }
+
+    fn check_read(&self, filename: &str) -> Result<(), FileError> {
+        self.sandbox.as_ref().map_or(Ok(()), |ss| ss.check_read(filename))
+    }
+
+    fn check_write(&self, filename: &str) -> Result<(), FileError> {
+        self.sandbox.as_ref().map_or(Ok(()), |ss| ss.check_write(filename))
+    }
+
+    //every file under a directory has to be inside too, or a symlink in it could be copied out
+    fn check_read_all(&self, from: &str) -> Result<(), FileError> {
+        if self.sandbox.is_some() && Path::new(from).is_dir() {
+            for file in list_dir(from)? {
+                self.check_read(&file)?;
+            }
+        }
+        self.check_read(from)
+    }
+
+    //forgets the file's contents and anything parsed from it, so the next read goes to disk
+    pub fn invalidate(&mut self, filename: &str) {
+        let key = clean_path(filename);
+        if let Some(cached) = self.files.remove(&key) {
+            self.cached_bytes -= cached.contents.as_bytes().len();
+        }
+        self.yamls.retain(|(name, _), _| *name != key);
+    }
+
+    //invalidates every file under the directory
+    pub fn invalidate_dir(&mut self, dir: &str) {
+        let prefix = format!("{}/", clean_path(dir).trim_end_matches('/'));
+        let under: Vec<String> = self.files.keys().filter(|ff| ff.starts_with(&prefix)).cloned().collect();
+        for filename in under {
+            self.invalidate(&filename);
+        }
+        self.yamls.retain(|(name, _), _| !name.starts_with(&prefix));
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+        self.yamls.clear();
+        self.cached_bytes = 0;
+    }
+
+    //drops the least recently read files, other than the one being kept, until the rest fit
+    fn evict(&mut self, keep: &str) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        while self.cached_bytes > budget {
+            let oldest = self.files.iter()
+                .filter(|(name, _)| name.as_str() != keep)
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(name, _)| name.to_owned());
+            match oldest {
+                Some(name) => {
+                    self.invalidate(&name);
+                    self.reads.evictions += 1;
+                },
+                None => return,
+            }
+        }
+    }
+
+    //only reads that miss the cache are checked against the sandbox, since anything in it was checked
+    //on the way in. files are cached under their cleaned name, the way the watcher reports them, so
+    //`./site/a.txt` and `site/a.txt` share an entry
+    fn load(&mut self, filename: &str) -> Result<&CachedFile, FileError> {
+        self.clock += 1;
+        let key = clean_path(filename);
+        match self.is_fresh(filename, &key) {
+            Some(true) => self.reads.hits += 1,
+            fresh => {
+                if fresh.is_some() {
+                    self.reads.reloads += 1;
+                    self.invalidate(filename);
+                } else {
+                    self.reads.misses += 1;
+                }
+                self.check_read(filename)?;
+                //stamped before reading, so a change made during the read is noticed next time
+                let stamp = if self.check_changes {stamp(filename)} else {None};
+                let contents = read_file(filename)?;
+                self.cached_bytes += contents.as_bytes().len();
+                self.files.insert(key.clone(), CachedFile{contents, stamp, last_used: 0});
+                self.evict(&key);
+            },
+        }
+        let cached = self.files.get_mut(&key).unwrap();
+        cached.last_used = self.clock;
+        Ok(cached)
+    }
+
+    fn is_fresh(&self, filename: &str, key: &str) -> Option<bool> {
+        self.files.get(key).map(|cached| !self.check_changes || stamp(filename) == cached.stamp)
+    }
+}
+
+impl Default for FileCache {
+    fn default() -> FileCache { FileCache::new() }
+}
+
+fn read_file(filename: &str) -> Result<Contents, FileError> {
     if Path::exists(Path::new(filename)) {
-        match fs::read_to_string(filename) {
-            Ok(strr) => Ok(strr),
+        match fs::read(filename) {
+            Ok(bytes) => Ok(match String::from_utf8(bytes) {
+                Ok(text) => Contents::Text(text),
+                Err(ee) => Contents::Bytes(ee.into_bytes()),
+            }),
             Err(_) => Err(FileError::FileCantBeRead(filename.to_owned())),
         }
     } else {
@@ -50,34 +373,79 @@ fn read_file(filename: &str) -> Result<String, FileError> {
     }
 }
 
+//leaves the file alone if it already holds exactly these bytes, so its modification time only moves
+//when it really changes. otherwise writes to a temporary file next to it and renames it into place,
+//so nothing ever sees a half written file. returns whether the file was written
+pub fn write_file(filename: &str, contents: &str) -> Result<bool, FileError> {
+    write_file_bytes(filename, contents.as_bytes())
+}
+
+pub fn write_file_bytes(filename: &str, contents: &[u8]) -> Result<bool, FileError> {
+    let path = Path::new(filename);
+    if let Ok(existing) = fs::read(path) {
+        if existing == contents {
+            return Ok(false);
+        }
+    }
+    let cant_write = |_| FileError::FileCantBeWritten(filename.to_owned());
+    if let Some(parent) = path.parent().filter(|pp| !pp.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(cant_write)?;
+    }
+    let name = path.file_name().ok_or(FileError::FileCantBeWritten(filename.to_owned()))?;
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), std::process::id()));
+    if let Err(ee) = fs::write(&temp, contents).and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(cant_write(ee));
+    }
+    Ok(true)
+}
+
 impl ReadsFiles for FileCache {
     fn read(&mut self, filename: &str) -> Result<&str, FileError> {
-        Ok(match self.files.entry(filename.to_owned()) {
-            Entry::Occupied(ee) => ee.into_mut(),
-            Entry::Vacant(ee) => ee.insert(read_file(filename)?),
-        })
+        match &self.load(filename)?.contents {
+            Contents::Text(text) => Ok(text),
+            Contents::Bytes(_) => Err(FileError::FileCantBeRead(filename.to_owned())),
+        }
     }
 
-    fn read_yaml(&mut self, filename: &str) -> Result<&YamlValue, YamlFileError> {
-        let contentsref = self.read(filename).map_err(|xx| YamlFileError::File(xx))?;
-        let contents = contentsref.to_owned();
-        Ok(match self.yamls.entry(filename.to_owned()) {
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        Ok(self.load(filename)?.contents.as_bytes())
+    }
+
+    fn exists(&mut self, filename: &str) -> bool {
+        self.check_read(filename).is_ok() && Path::new(filename).is_file()
+    }
+
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
+        let contents = self.read(filename).map_err(YamlFileError::File)?.to_owned();
+        Ok(match self.yamls.entry((clean_path(filename), format)) {
             Entry::Occupied(ee) => ee.into_mut(),
-            Entry::Vacant(ee) => {
-                ee.insert(load_yaml(&contents).map_err(|xx| YamlFileError::Yaml(xx))?)
-            }
+            Entry::Vacant(ee) => ee.insert(load_data(&contents, format)?),
         })
     }
 
     fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
-        fs::write(filename, contents).map_err(|xx| FileError::FileCantBeWritten(filename.to_owned()))
+        self.write_bytes(filename, contents.as_bytes())
+    }
+
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        self.check_write(filename)?;
+        if write_file_bytes(filename, contents)? {
+            self.stats.written += 1;
+            self.invalidate(filename);
+        } else {
+            self.stats.unchanged += 1;
+        }
+        Ok(())
     }
 
     fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.check_read_all(from)?;
+        self.check_write(to)?;
         let from_path = PathBuf::from(from);
         let to_path = PathBuf::from(to);
         if from_path.is_file() {
-            match fs::copy(from, to) {
+            match link_or_copy(&from_path, &to_path, CopyMode::Copy) {
                 Ok(_) => Ok(()),
                 Err(ee) => Err(FileError::FilesCantBeCopied(from.to_owned())),
             }
@@ -89,6 +457,159 @@ impl ReadsFiles for FileCache {
             }
         }
     }
+
+    fn copy_file(&self, from: &str, to: &str, mode: CopyMode) -> Result<(), FileError> {
+        self.check_read(from)?;
+        self.check_write(to)?;
+        link_or_copy(Path::new(from), Path::new(to), mode).map_err(|_| FileError::FilesCantBeCopied(from.to_owned()))
+    }
+
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        self.check_read(dir)?;
+        list_dir(dir)
+    }
+
+    fn remove(&mut self, filename: &str) -> Result<(), FileError> {
+        self.check_write(filename)?;
+        self.invalidate(filename);
+        fs::remove_file(filename).map_err(|_| FileError::FileCantBeRemoved(filename.to_owned()))
+    }
+}
+
+//a FileCache that can be shared between threads. every handle keeps its own copies of what it has
+//read, so only the first read of a file on each handle has to take the lock
+pub struct SharedFileCache {
+    shared: Arc<Mutex<FileCache>>,
+    files: HashMap<String, String>,
+    bytes: HashMap<String, Vec<u8>>,
+    yamls: HashMap<(String, DataFormat), YamlValue>,
+}
+
+impl SharedFileCache {
+    pub fn new(cache: FileCache) -> SharedFileCache {
+        SharedFileCache{shared: Arc::new(Mutex::new(cache)), files: HashMap::new(), bytes: HashMap::new(), yamls: HashMap::new()}
+    }
+
+    //drops this handle's copies of the file
+    fn forget(&mut self, filename: &str) {
+        self.files.remove(filename);
+        self.bytes.remove(filename);
+        self.yamls.retain(|(name, _), _| name != filename);
+    }
+
+    //runs something against the underlying cache, e.g. to invalidate files
+    pub fn with_cache<T>(&self, func: impl FnOnce(&mut FileCache) -> T) -> T {
+        func(&mut self.shared.lock().unwrap())
+    }
+}
+
+impl ForksFiles for SharedFileCache {
+    fn fork(&self) -> SharedFileCache {
+        SharedFileCache{shared: self.shared.clone(), files: HashMap::new(), bytes: HashMap::new(), yamls: HashMap::new()}
+    }
+}
+
+impl ReadsFiles for SharedFileCache {
+    fn read(&mut self, filename: &str) -> Result<&str, FileError> {
+        Ok(match self.files.entry(filename.to_owned()) {
+            Entry::Occupied(ee) => ee.into_mut(),
+            Entry::Vacant(ee) => {
+                let contents = self.shared.lock().unwrap().read(filename)?.to_owned();
+                ee.insert(contents)
+            }
+        })
+    }
+
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        Ok(match self.bytes.entry(filename.to_owned()) {
+            Entry::Occupied(ee) => ee.into_mut(),
+            Entry::Vacant(ee) => {
+                let contents = self.shared.lock().unwrap().read_bytes(filename)?.to_vec();
+                ee.insert(contents)
+            }
+        })
+    }
+
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
+        Ok(match self.yamls.entry((filename.to_owned(), format)) {
+            Entry::Occupied(ee) => ee.into_mut(),
+            Entry::Vacant(ee) => {
+                let loaded = self.shared.lock().unwrap().read_data(filename, format)?.to_owned();
+                ee.insert(loaded)
+            }
+        })
+    }
+
+    fn exists(&mut self, filename: &str) -> bool {
+        self.shared.lock().unwrap().exists(filename)
+    }
+
+    fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
+        self.write_bytes(filename, contents.as_bytes())
+    }
+
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        self.forget(filename);
+        self.shared.lock().unwrap().write_bytes(filename, contents)
+    }
+
+    fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.shared.lock().unwrap().copy_files(from, to)
+    }
+
+    fn copy_file(&self, from: &str, to: &str, mode: CopyMode) -> Result<(), FileError> {
+        self.shared.lock().unwrap().copy_file(from, to, mode)
+    }
+
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        self.shared.lock().unwrap().list_files(dir)
+    }
+
+    fn remove(&mut self, filename: &str) -> Result<(), FileError> {
+        self.forget(filename);
+        self.shared.lock().unwrap().remove(filename)
+    }
+}
+
+fn list_dir(dir: &str) -> Result<Vec<String>, FileError> {
+    if !Path::new(dir).is_dir() {
+        return Err(FileError::FileNotFound(dir.to_owned()));
+    }
+    let pattern = format!("{}/**/*", glob::Pattern::escape(dir.trim_end_matches('/')));
+    let paths = glob(&pattern).map_err(|_| FileError::FileCantBeRead(dir.to_owned()))?;
+    let mut files: Vec<String> = vec![];
+    for path in paths {
+        let path = path.map_err(|_| FileError::FileCantBeRead(dir.to_owned()))?;
+        if path.is_file() {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+//links point at the absolute path of the source, so they still work from wherever the output ends up.
+//whatever is already at `to` is removed first, since copying over a link left by an earlier run in
+//another mode would write through it into the source
+fn link_or_copy(from: &Path, to: &Path, mode: CopyMode) -> io::Result<()> {
+    if let Some(parent) = to.parent().filter(|pp| !pp.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    if let Ok(meta) = fs::symlink_metadata(to) {
+        //the very same path, not a link to it, so there's nothing to do
+        if !meta.file_type().is_symlink() && fs::canonicalize(to)? == fs::canonicalize(from)? {
+            return Ok(());
+        }
+        fs::remove_file(to)?;
+    }
+    match mode {
+        CopyMode::Copy => fs::copy(from, to).map(|_| ()),
+        CopyMode::Hardlink => fs::hard_link(from, to),
+        #[cfg(unix)]
+        CopyMode::Symlink => std::os::unix::fs::symlink(fs::canonicalize(from)?, to),
+        #[cfg(windows)]
+        CopyMode::Symlink => std::os::windows::fs::symlink_file(fs::canonicalize(from)?, to),
+    }
 }
 
 fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
@@ -99,7 +620,7 @@ fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()>
         if ty.is_dir() {
             copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
         } else {
-            fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
+            link_or_copy(&entry.path(), &dst.as_ref().join(entry.file_name()), CopyMode::Copy)?;
         }
     }
     Ok(())
@@ -2,7 +2,19 @@ pub mod template;
 pub mod parsers;
 pub mod yaml;
 pub mod io;
+pub mod memory;
 pub mod utils;
 pub mod pipes;
 pub mod build;
+pub mod copy;
+pub mod assets;
+pub mod minify;
+pub mod transform;
+pub mod data;
+pub mod incremental;
+pub mod watch;
+pub mod serve;
+pub mod parallel;
+pub mod plan;
+pub mod prune;
 pub mod tests;
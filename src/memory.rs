@@ -0,0 +1,194 @@
+use crate::io::{ReadsFiles, FileError, CopyMode, write_file_bytes};
+use crate::utils::clean_path;
+use crate::yaml::{YamlValue, YamlFileError, DataFormat, load_data};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::path::Path;
+
+//a whole filesystem held in memory. directories exist as long as there's a file in them, and paths
+//are cleaned up first so `a/./b` and `a/x/../b` are the same file. contents are kept as bytes, so
+//images and the like survive a copy, but only text can be read as a string
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    //copy_files only gets &self, so these sit in RefCells. everything else goes through get_mut
+    files: RefCell<HashMap<String, Vec<u8>>>,
+    yamls: RefCell<HashMap<(String, DataFormat), YamlValue>>,
+}
+
+fn dir_prefix(dir: &str) -> String {
+    let dir = clean_path(dir);
+    if dir.is_empty() || dir == "/" {
+        dir
+    } else {
+        format!("{}/", dir)
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    pub fn from_files<K: AsRef<str>, V: Into<Vec<u8>>>(files: impl IntoIterator<Item = (K, V)>) -> MemoryFs {
+        let mut memory = MemoryFs::new();
+        for (filename, contents) in files {
+            memory.set_file(filename.as_ref(), contents);
+        }
+        memory
+    }
+
+    //every file under the directory, named relative to it
+    pub fn snapshot(dir: &str) -> Result<MemoryFs, FileError> {
+        let root = Path::new(dir);
+        if !root.is_dir() {
+            return Err(FileError::FileNotFound(dir.to_owned()));
+        }
+        let mut memory = MemoryFs::new();
+        snapshot_dir(root, root, &mut memory)?;
+        Ok(memory)
+    }
+
+    //writes every file out under the directory, leaving alone any that already match
+    pub fn dump(&mut self, dir: &str) -> Result<(), FileError> {
+        for (filename, contents) in self.files.get_mut().iter() {
+            let path = Path::new(dir).join(filename.trim_start_matches('/'));
+            write_file_bytes(&path.to_string_lossy(), contents)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_file(&mut self, filename: &str, contents: impl Into<Vec<u8>>) {
+        self.insert(clean_path(filename), contents.into());
+    }
+
+    pub fn filenames(&mut self) -> Vec<String> {
+        let mut filenames: Vec<String> = self.files.get_mut().keys().cloned().collect();
+        filenames.sort();
+        filenames
+    }
+
+    fn forget(&self, filename: &str) {
+        self.yamls.borrow_mut().retain(|(name, _), _| name != filename);
+    }
+
+    fn insert(&self, filename: String, contents: Vec<u8>) {
+        self.forget(&filename);
+        self.files.borrow_mut().insert(filename, contents);
+    }
+
+    fn is_dir(&self, dir: &str) -> bool {
+        let prefix = dir_prefix(dir);
+        self.files.borrow().keys().any(|ff| ff.starts_with(&prefix))
+    }
+}
+
+fn snapshot_dir(root: &Path, dir: &Path, memory: &mut MemoryFs) -> Result<(), FileError> {
+    let cant_read = |_| FileError::FileCantBeRead(dir.to_string_lossy().into_owned());
+    for entry in fs::read_dir(dir).map_err(cant_read)? {
+        let path = entry.map_err(cant_read)?.path();
+        if path.is_dir() {
+            snapshot_dir(root, &path, memory)?;
+        } else {
+            let contents = fs::read(&path).map_err(|_| FileError::FileCantBeRead(path.to_string_lossy().into_owned()))?;
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            memory.set_file(&relative.to_string_lossy(), contents);
+        }
+    }
+    Ok(())
+}
+
+impl ReadsFiles for MemoryFs {
+    fn read(&mut self, filename: &str) -> Result<&str, FileError> {
+        match self.files.get_mut().get(&clean_path(filename)) {
+            Some(contents) => std::str::from_utf8(contents).map_err(|_| FileError::FileCantBeRead(filename.to_owned())),
+            None => Err(FileError::FileNotFound(filename.to_owned())),
+        }
+    }
+
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        match self.files.get_mut().get(&clean_path(filename)) {
+            Some(contents) => Ok(contents),
+            None => Err(FileError::FileNotFound(filename.to_owned())),
+        }
+    }
+
+    fn exists(&mut self, filename: &str) -> bool {
+        self.files.get_mut().contains_key(&clean_path(filename))
+    }
+
+    fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
+        self.set_file(filename, contents);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        self.set_file(filename, contents);
+        Ok(())
+    }
+
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
+        let contents = self.read(filename).map_err(YamlFileError::File)?.to_owned();
+        Ok(match self.yamls.get_mut().entry((clean_path(filename), format)) {
+            Entry::Occupied(ee) => ee.into_mut(),
+            Entry::Vacant(ee) => ee.insert(load_data(&contents, format)?),
+        })
+    }
+
+    //like on disk: a file is copied to `to`, a directory has everything in it copied under `to`
+    fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        let (from_clean, to_clean) = (clean_path(from), clean_path(to));
+        let file = self.files.borrow().get(&from_clean).cloned();
+        if let Some(contents) = file {
+            self.insert(to_clean, contents);
+            return Ok(());
+        }
+        if self.files.borrow().contains_key(&to_clean) {
+            return Err(FileError::CantCopyDirIntoFile(from.to_owned(), to.to_owned()));
+        }
+        let prefix = dir_prefix(from);
+        let copied: Vec<(String, Vec<u8>)> = self.files.borrow().iter()
+            .filter_map(|(ff, contents)| ff.strip_prefix(&prefix).map(|rest| (rest.to_owned(), contents.to_owned())))
+            .collect();
+        if copied.is_empty() {
+            return Err(FileError::FilesCantBeCopied(from.to_owned()));
+        }
+        let to_prefix = dir_prefix(to);
+        for (rest, contents) in copied {
+            self.insert(format!("{}{}", to_prefix, rest), contents);
+        }
+        Ok(())
+    }
+
+    //there's nothing to link in memory, so every mode copies
+    fn copy_file(&self, from: &str, to: &str, _mode: CopyMode) -> Result<(), FileError> {
+        let file = self.files.borrow().get(&clean_path(from)).cloned();
+        match file {
+            Some(contents) => {
+                self.insert(clean_path(to), contents);
+                Ok(())
+            },
+            None => Err(FileError::FilesCantBeCopied(from.to_owned())),
+        }
+    }
+
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        if !self.is_dir(dir) {
+            return Err(FileError::FileNotFound(dir.to_owned()));
+        }
+        let prefix = dir_prefix(dir);
+        let mut found: Vec<String> = self.files.get_mut().keys().filter(|ff| ff.starts_with(&prefix)).cloned().collect();
+        found.sort();
+        Ok(found)
+    }
+
+    fn remove(&mut self, filename: &str) -> Result<(), FileError> {
+        let filename_clean = clean_path(filename);
+        self.forget(&filename_clean);
+        match self.files.get_mut().remove(&filename_clean) {
+            Some(_) => Ok(()),
+            None => Err(FileError::FileNotFound(filename.to_owned())),
+        }
+    }
+}
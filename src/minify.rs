@@ -0,0 +1,313 @@
+//whitespace and comment stripping for outputs, picked by their extension. everything here is meant
+//to be safe rather than small: text is only ever collapsed, never reflowed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MinifyOptions {
+    //also covers inline `<style>` and `<script>` when css and js are on
+    pub html: bool,
+    pub css: bool,
+    pub js: bool,
+}
+
+impl MinifyOptions {
+    //what a production build uses: everything on
+    pub fn production() -> MinifyOptions {
+        MinifyOptions{html: true, css: true, js: true}
+    }
+
+    pub fn is_off(&self) -> bool {
+        *self == MinifyOptions::default()
+    }
+
+    //None when outputs with this extension are left as they are
+    pub fn minify(&self, output: &str, contents: &str) -> Option<String> {
+        let name = output.rsplit('/').next().unwrap_or(output);
+        let ext = name.rfind('.').map_or("", |ii| &name[ii + 1..]).to_ascii_lowercase();
+        match ext.as_str() {
+            "html" | "htm" if self.html => Some(minify_html(contents, self)),
+            "css" if self.css => Some(minify_css(contents)),
+            "js" | "mjs" if self.js => Some(minify_js(contents)),
+            _ => None,
+        }
+    }
+}
+
+//elements whose contents are copied as they are, or handed to the css or js minifier
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+//comments go, except conditional ones, and runs of whitespace between tags become a single space.
+//tags themselves are left alone so attribute values keep their spacing
+pub fn minify_html(html: &str, options: &MinifyOptions) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |ii| ii + 3);
+            if rest.starts_with("<!--[if") || rest.starts_with("<!--<![endif]") {
+                out.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            let end = tag_end(rest);
+            let tag = &rest[..end];
+            out.push_str(tag);
+            rest = &rest[end..];
+            let name = tag_name(tag);
+            if RAW_ELEMENTS.contains(&name.as_str()) {
+                let close = find_ignoring_case(rest, &format!("</{}", name)).unwrap_or(rest.len());
+                let contents = &rest[..close];
+                match name.as_str() {
+                    "style" if options.css => out.push_str(&minify_css(contents)),
+                    "script" if options.js && is_javascript(tag) => out.push_str(&minify_js(contents)),
+                    _ => out.push_str(contents),
+                }
+                rest = &rest[close..];
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            collapse_whitespace(&rest[..end], &mut out);
+            rest = &rest[end..];
+        }
+    }
+    out
+}
+
+//just past the `>` that closes the tag, skipping any inside quoted attribute values
+fn tag_end(rest: &str) -> usize {
+    let mut quote: Option<char> = None;
+    for (ii, cc) in rest.char_indices() {
+        match (quote, cc) {
+            (Some(qq), _) if cc == qq => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(cc),
+            (None, '>') => return ii + 1,
+            _ => (),
+        }
+    }
+    rest.len()
+}
+
+//lowercased, empty for closing tags and doctypes
+fn tag_name(tag: &str) -> String {
+    tag[1..].chars().take_while(|cc| cc.is_ascii_alphanumeric()).collect::<String>().to_ascii_lowercase()
+}
+
+//the needle has to be lowercase ascii. compares in place rather than lowercasing the haystack, which
+//would copy the rest of the page for every raw element
+fn find_ignoring_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.as_bytes().windows(needle.len()).position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+//no type, or a javascript one. anything else, like json or a client side template, is left alone
+fn is_javascript(tag: &str) -> bool {
+    let tag = tag.to_ascii_lowercase();
+    match tag.find("type=") {
+        None => true,
+        Some(ii) => {
+            let value = tag[ii + 5..].trim_start_matches(['"', '\'']);
+            value.starts_with("module") || value.starts_with("text/javascript") || value.starts_with("application/javascript")
+        },
+    }
+}
+
+//a dropped comment can leave whitespace on both sides of it, which still only makes the one space
+fn collapse_whitespace(text: &str, out: &mut String) {
+    let mut in_space = out.ends_with(' ');
+    for cc in text.chars() {
+        if cc.is_whitespace() {
+            if !in_space {
+                out.push(' ');
+            }
+            in_space = true;
+        } else {
+            out.push(cc);
+            in_space = false;
+        }
+    }
+}
+
+//comments go, except `/*!` ones, whitespace runs become one space, and the space around braces,
+//semicolons and commas goes, as does the space after a colon inside a block, where it always starts
+//a value. in a selector `a :hover` and `a:hover` mean different things. strings are copied as they are
+pub fn minify_css(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut pending_space = false;
+    let mut depth = 0;
+    while let Some(cc) = chars.next() {
+        match cc {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let keep = chars.peek() == Some(&'!');
+                let mut comment = String::from("/*");
+                let mut last = ' ';
+                for next in chars.by_ref() {
+                    comment.push(next);
+                    if last == '*' && next == '/' {
+                        break;
+                    }
+                    last = next;
+                }
+                if keep {
+                    push_css_space(&mut out, &mut pending_space);
+                    out.push_str(&comment);
+                } else {
+                    pending_space = true;
+                }
+            },
+            '"' | '\'' => {
+                push_css_space(&mut out, &mut pending_space);
+                out.push(cc);
+                copy_string(cc, &mut chars, &mut out);
+            },
+            _ if cc.is_whitespace() => pending_space = true,
+            '{' | '}' | ';' | ',' => {
+                match cc {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if out.ends_with(';') {
+                            out.pop();
+                        }
+                    },
+                    _ => (),
+                }
+                out.push(cc);
+                pending_space = false;
+            },
+            ':' if depth > 0 => {
+                push_css_space(&mut out, &mut pending_space);
+                out.push(cc);
+                while chars.peek().is_some_and(|next| next.is_whitespace()) {
+                    chars.next();
+                }
+            },
+            _ => {
+                push_css_space(&mut out, &mut pending_space);
+                out.push(cc);
+            },
+        }
+    }
+    out.trim().to_owned()
+}
+
+//a space is only needed if it isn't next to punctuation that ends things anyway
+fn push_css_space(out: &mut String, pending_space: &mut bool) {
+    if *pending_space && !out.is_empty() && !out.ends_with(['{', '}', ';', ',']) {
+        out.push(' ');
+    }
+    *pending_space = false;
+}
+
+fn copy_string(quote: char, chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String) {
+    while let Some(cc) = chars.next() {
+        out.push(cc);
+        if cc == '\\' {
+            if let Some(escaped) = chars.next() {
+                out.push(escaped);
+            }
+        } else if cc == quote {
+            return;
+        }
+    }
+}
+
+//keywords after which a `/` starts a regular expression rather than dividing
+const REGEX_KEYWORDS: [&str; 14] = [
+    "return", "typeof", "case", "do", "else", "in", "of", "void", "yield", "await", "delete", "new", "throw", "instanceof",
+];
+
+//drops comments and blank lines and trims whitespace, keeping line breaks so automatic semicolon
+//insertion still sees them. strings, template literals and regular expressions are copied as they are
+pub fn minify_js(js: &str) -> String {
+    let mut out = String::with_capacity(js.len());
+    let mut chars = js.chars().peekable();
+    //whitespace seen since the last thing written, and whether it held a line break
+    let mut pending: Option<bool> = None;
+    while let Some(cc) = chars.next() {
+        match cc {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|next| *next != '\n') {
+                    chars.next();
+                }
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                let mut newline = false;
+                for next in chars.by_ref() {
+                    newline |= next == '\n';
+                    if last == '*' && next == '/' {
+                        break;
+                    }
+                    last = next;
+                }
+                pending = Some(newline || pending == Some(true));
+            },
+            _ if cc.is_whitespace() => pending = Some(cc == '\n' || pending == Some(true)),
+            _ => {
+                let starts_regex = cc == '/' && regex_can_follow(&out);
+                if let Some(newline) = pending.take() {
+                    push_js_space(&mut out, newline, cc);
+                }
+                out.push(cc);
+                match cc {
+                    '"' | '\'' | '`' => copy_string(cc, &mut chars, &mut out),
+                    '/' if starts_regex => copy_regex(&mut chars, &mut out),
+                    _ => (),
+                }
+            },
+        }
+    }
+    out.trim().to_owned()
+}
+
+fn is_word_char(cc: char) -> bool {
+    cc.is_alphanumeric() || cc == '_' || cc == '$' || cc == '\\' || !cc.is_ascii()
+}
+
+//a line break is kept unless it's the first thing. a space is kept only between two word characters,
+//or where dropping it would glue `+ +`, `- -` or `/ /` into something else
+fn push_js_space(out: &mut String, newline: bool, next: char) {
+    let last = match out.chars().last() {
+        Some(last) => last,
+        None => return,
+    };
+    if newline {
+        out.push('\n');
+    } else if (is_word_char(last) && is_word_char(next)) || (last == next && matches!(next, '+' | '-' | '/')) {
+        out.push(' ');
+    }
+}
+
+fn regex_can_follow(out: &str) -> bool {
+    match out.trim_end().chars().last() {
+        None => true,
+        Some(last) if "(,=:[!&|?{};+-*%<>~^".contains(last) => true,
+        Some(last) if is_word_char(last) => {
+            let word: String = out.trim_end().chars().rev().take_while(|cc| is_word_char(*cc)).collect();
+            let word: String = word.chars().rev().collect();
+            REGEX_KEYWORDS.contains(&word.as_str())
+        },
+        _ => false,
+    }
+}
+
+//up to and including the closing `/`, which doesn't count inside a `[...]` class
+fn copy_regex(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String) {
+    let mut in_class = false;
+    while let Some(cc) = chars.next() {
+        out.push(cc);
+        match cc {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+            },
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => return,
+            '\n' => return,
+            _ => (),
+        }
+    }
+}
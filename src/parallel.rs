@@ -0,0 +1,100 @@
+use crate::build::{
+    BuildAction, BuildContext, BuildError, EntryError,
+    build_multiple_pages_entries, build_page, collect_page_error, many_or_ok, push_action_error, report_errors,
+};
+use crate::io::ForksFiles;
+use crate::pipes::PipeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+//zero means one thread per core
+pub fn thread_count(threads: usize) -> usize {
+    if threads > 0 {
+        threads
+    } else {
+        thread::available_parallelism().map(|nn| nn.get()).unwrap_or(1)
+    }
+}
+
+//runs the function over every item spread across the threads, each with its own fork of the files.
+//the results come back in the same order as the items no matter which thread got to them first
+pub fn map_parallel<T: Sync, R: Send, IO: ForksFiles>(
+    items: &[T],
+    threads: usize,
+    io: &IO,
+    func: impl Fn(&T, &mut IO) -> R + Sync
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let workers = thread_count(threads).min(items.len()).max(1);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| {
+            let mut forked = io.fork();
+            let next = &next;
+            let func = &func;
+            scope.spawn(move || {
+                let mut done = vec![];
+                loop {
+                    let ii = next.fetch_add(1, Ordering::Relaxed);
+                    if ii >= items.len() {
+                        break;
+                    }
+                    done.push((ii, func(&items[ii], &mut forked)));
+                }
+                done
+            })
+        }).collect();
+        handles.into_iter().flat_map(|hh| hh.join().unwrap()).collect()
+    });
+    results.sort_by_key(|(ii, _)| *ii);
+    results.into_iter().map(|(_, rr)| rr).collect()
+}
+
+impl BuildAction {
+    //like run_in, but the pages of a BuildMultiplePages are rendered across threads
+    pub fn run_parallel(
+        &self,
+        ctx: &BuildContext,
+        pipes: &PipeMap,
+        io: &mut impl ForksFiles,
+        threads: usize
+    ) -> Result<(), BuildError> {
+        match self {
+            BuildAction::BuildMultiplePages{default_params, on} => {
+                let mut errors: Vec<EntryError> = vec![];
+                let (defaults, mapped) = build_multiple_pages_entries(ctx, default_params, on, pipes, io, &mut errors)?;
+                let results = map_parallel(&mapped, threads, io, |ii, io| {
+                    build_page(ctx, &ii.2, &ii.3, &defaults, &ii.0, pipes, io)
+                });
+                for (ii, result) in mapped.into_iter().zip(results) {
+                    if let Err(ee) = result {
+                        collect_page_error(ctx, &mut errors, ii.1, ii.4, ee)?;
+                    }
+                }
+                many_or_ok(errors)
+            },
+            _ => self.run_in(ctx, pipes, io),
+        }
+    }
+}
+
+//runs the actions one after another in the order given, as run_actions does, so an action can use
+//what an earlier one wrote and later copies win over earlier ones. the pages of each
+//BuildMultiplePages are what's spread across the threads. errors are reported like run_actions
+pub fn run_actions_parallel(
+    actions: &[BuildAction],
+    ctx: &BuildContext,
+    pipes: &PipeMap,
+    io: &mut impl ForksFiles,
+    threads: usize
+) -> Result<(), BuildError> {
+    ctx.start_run();
+    let mut errors: Vec<EntryError> = vec![];
+    for (ii, action) in actions.iter().enumerate() {
+        match action.run_parallel(ctx, pipes, io, threads) {
+            Ok(()) => (),
+            Err(error) if ctx.fail_fast => return Err(error),
+            Err(error) => push_action_error(&mut errors, ii, error),
+        }
+    }
+    report_errors(errors)
+}
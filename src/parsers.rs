@@ -4,6 +4,7 @@ use crate::template::{
 use crate::pipes::{
   Pipe
 };
+use crate::yaml::DataFormat;
 use pest::{
   iterators::{Pair, Pairs},
   error::Error,
@@ -20,9 +21,11 @@ fn parse_ast_node(pair: Pair<Rule>) -> TemplateElement {
     Rule::plain_text => TemplateElement::PlainText(pair.as_str().to_string()),
     Rule::replacement => {
       let mut iter = pair.into_inner();
-      TemplateElement::Replace {
-        value: parse_value(iter.next().unwrap()),
-        pipe: parse_pipes(&mut iter.next().unwrap().into_inner()) 
+      let first = iter.next().unwrap();
+      let pipe = parse_pipes(&mut iter.next().unwrap().into_inner());
+      match first.as_rule() {
+        Rule::literal => TemplateElement::Literal{text: first.into_inner().as_str().to_string(), pipe},
+        _ => TemplateElement::Replace{value: parse_value(first), pipe},
       }
     } ,
     Rule::snippet => parse_file_element(true, &mut pair.into_inner()),
@@ -97,18 +100,40 @@ fn parse_values(pairs: &mut Pairs<Rule>) -> Vec<TemplateValue> {
   pairs.map(|ii| { println!("|{}|", ii.as_str()); parse_value(ii) }).collect()
 }
 
+//the optional format suffix on in-file clauses, followed by the clause's contents
+fn parse_data_format<'a>(pairs: &mut Pairs<'a, Rule>) -> (Option<DataFormat>, Pair<'a, Rule>) {
+  let next = pairs.next().unwrap();
+  match next.as_rule() {
+    Rule::data_format => (
+      DataFormat::from_extension(next.as_str().trim_start_matches('-')),
+      pairs.next().unwrap()
+    ),
+    _ => (None, next),
+  }
+}
+
 fn parse_for_element(pairs: &mut Pairs<Rule>) -> TemplateElement {
   let name = pairs.next().unwrap().as_str().to_string();
   let mut values: Vec<TemplateValue> = Vec::new();
   let mut filenames: Vec<String> = Vec::new();
   let mut files_at: Vec<TemplateValue> = Vec::new();
+  let mut file_format: Option<DataFormat> = None;
+  let mut file_at_format: Option<DataFormat> = None;
   let mut main: Option<Vec<TemplateElement>> = Option::None;
   while main.is_none() {
     let next = pairs.next().unwrap();
     match next.as_rule() {
       Rule::for_in => values = parse_values(&mut next.into_inner().next().unwrap().into_inner()),
-      Rule::for_in_file => filenames = parse_filenames(&mut next.into_inner().next().unwrap().into_inner()),
-      Rule::for_in_file_at => files_at = parse_values(&mut next.into_inner().next().unwrap().into_inner()),
+      Rule::for_in_file => {
+        let (format, inner) = parse_data_format(&mut next.into_inner());
+        file_format = format;
+        filenames = parse_filenames(&mut inner.into_inner());
+      },
+      Rule::for_in_file_at => {
+        let (format, inner) = parse_data_format(&mut next.into_inner());
+        file_at_format = format;
+        files_at = parse_values(&mut inner.into_inner());
+      },
       Rule::ast => main = Some(next.into_inner().map(parse_ast_node).collect()),
       _ => unreachable!("for loop options"),
     };
@@ -117,7 +142,9 @@ fn parse_for_element(pairs: &mut Pairs<Rule>) -> TemplateElement {
     None => vec![],
     Some(ss) => ss.into_inner().map(parse_ast_node).collect(),
   };
-  TemplateElement::For{name, values, filenames, files_at, main: main.unwrap(), separator}
+  TemplateElement::For{
+    name, values, filenames, files_at, file_format, file_at_format, main: main.unwrap(), separator
+  }
 }
 
 pub fn parse_template_string(input: &str) -> Result<Vec<TemplateElement>, Error<Rule>> {
@@ -2,11 +2,13 @@ use crate::yaml::{
     YamlValue,
     new_yaml_map,
 };
+use crate::utils::{base64, mime_type};
 use crate::io::{ReadsFiles, ReadsFilesImpl};
 use crate::template::{
-    TemplateElement, TemplateError, render_elements
+    TemplateElement, TemplateError, RenderOptions, render_elements
 };
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Pipe {
@@ -14,17 +16,22 @@ pub struct Pipe {
     pub params: Vec<String>
 }
 
+//has to stay Send + Sync so pages can be rendered on several threads at once
 pub enum PipeDefinition {
     Template(Vec<TemplateElement>),
     Fn(
         fn(
             &YamlValue,
             &PipeMap,
-            &ReadsFilesImpl,
+            &mut ReadsFilesImpl,
         ) -> Result<YamlValue, String>
-    )
+    ),
+    //for pipes that need state of their own, like the asset manifest
+    Closure(Arc<PipeFn>),
 }
 
+pub type PipeFn = dyn Fn(&YamlValue, &PipeMap, &mut ReadsFilesImpl) -> Result<YamlValue, String> + Send + Sync;
+
 pub type PipeMap = HashMap<String, PipeDefinition>;
 pub fn new_pipe_map() -> PipeMap { HashMap::new() }
 
@@ -32,6 +39,7 @@ pub fn execute_pipe<'a>(
     value: &'a YamlValue,
     pipe: &str,
     pipemap: &'a PipeMap,
+    options: &RenderOptions,
     io: &mut impl ReadsFiles
 ) -> Result<YamlValue, TemplateError> {
     let mut map = new_yaml_map();
@@ -45,21 +53,58 @@ pub fn execute_pipe<'a>(
     let input = YamlValue::Hash(params_map.clone());
     match pipemap.get(pipe) {
         Some(PipeDefinition::Template(elements)) => {
-            let rendered = render_elements(elements, params_map, pipemap, io)?;
+            let rendered = render_elements(elements, params_map, pipemap, options, io)?;
             Ok(YamlValue::String(rendered))
         },
         Some(PipeDefinition::Fn(func)) => {
-            let ioimpl: ReadsFilesImpl = ReadsFilesImpl {
-                read: &|filename| io.read(filename).map(|ii| ii.to_owned())
-            };
-            match func(&input, pipemap, &ioimpl) {
+            let mut ioimpl = ReadsFilesImpl{io};
+            match func(&input, pipemap, &mut ioimpl) {
                 Ok(strr) => Ok(strr),
                 Err(ee) => Err(TemplateError::PipeExecutionError(ee))
             }
         },
+        Some(PipeDefinition::Closure(func)) => {
+            let mut ioimpl = ReadsFilesImpl{io};
+            func(&input, pipemap, &mut ioimpl).map_err(TemplateError::PipeExecutionError)
+        },
         None => Err(TemplateError::PipeMissing(pipe.to_owned()))
     }
 }
+//the file a pipe was given, as in `{{ logo.png | data_uri }}`
+fn piped_file(input: &YamlValue) -> Result<&str, String> {
+    match &input["it"] {
+        YamlValue::String(filename) => Ok(filename),
+        _ => Err("Pipe expects a filename but it got something else".to_owned())
+    }
+}
+
+fn read_piped_file(input: &YamlValue, io: &mut ReadsFilesImpl) -> Result<Vec<u8>, String> {
+    let filename = piped_file(input)?;
+    io.read_bytes(filename).map_err(|ee| format!("{:?}", ee))
+}
+
+//the file as a data uri, typed by its extension
+fn data_uri_pipe(input: &YamlValue, _pipes: &PipeMap, io: &mut ReadsFilesImpl) -> Result<YamlValue, String> {
+    let contents = read_piped_file(input, io)?;
+    let mime = mime_type(piped_file(input)?).replace("; ", ";");
+    Ok(YamlValue::String(format!("data:{};base64,{}", mime, base64(&contents))))
+}
+
+fn base64_pipe(input: &YamlValue, _pipes: &PipeMap, io: &mut ReadsFilesImpl) -> Result<YamlValue, String> {
+    Ok(YamlValue::String(base64(&read_piped_file(input, io)?)))
+}
+
+//in bytes
+fn file_size_pipe(input: &YamlValue, _pipes: &PipeMap, io: &mut ReadsFilesImpl) -> Result<YamlValue, String> {
+    Ok(YamlValue::Integer(read_piped_file(input, io)?.len() as i64))
+}
+
+//pipes that take a filename and work on the file's bytes: `data_uri`, `base64` and `file_size`
+pub fn add_file_pipes(pipemap: &mut PipeMap) {
+    pipemap.insert("data_uri".to_owned(), PipeDefinition::Fn(data_uri_pipe));
+    pipemap.insert("base64".to_owned(), PipeDefinition::Fn(base64_pipe));
+    pipemap.insert("file_size".to_owned(), PipeDefinition::Fn(file_size_pipe));
+}
 /*
 pub fn pipe_success(
     func: fn(&YamlValue, &PipeMap, &ReadsFilesImpl) -> String,
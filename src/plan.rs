@@ -0,0 +1,289 @@
+use crate::build::{
+    BuildAction, BuildContext, BuildError, EntryError, ParamsSource,
+    build_multiple_pages_entries, build_page, collect_page_error, error_summary, many_or_ok, push_action_error,
+};
+use crate::io::{ReadsFiles, FileError, CopyMode};
+use crate::pipes::PipeMap;
+use crate::yaml::{YamlValue, YamlFileError, DataFormat, new_yaml_map, insert_value, to_json};
+use std::cell::RefCell;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanStatus {
+    New,
+    Changed,
+    Unchanged,
+}
+
+impl fmt::Display for PlanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanStatus::New => write!(f, "new"),
+            PlanStatus::Changed => write!(f, "changed"),
+            PlanStatus::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+//a file the build would have written. the input and source are filled in by plan_actions when it
+//knows which page the write came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedWrite {
+    pub output: String,
+    pub input: Option<String>,
+    pub source: Option<ParamsSource>,
+    pub index: Option<usize>,
+    pub bytes: usize,
+    pub status: PlanStatus,
+    contents: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCopy {
+    pub from: String,
+    pub to: String,
+}
+
+//wraps a ReadsFiles and keeps every write and copy to itself instead of doing it. reads of a file
+//that's been written see the new contents, so later actions behave like they would in a real build
+pub struct RecordingFiles<'a, R: ReadsFiles> {
+    inner: &'a mut R,
+    writes: Vec<PlannedWrite>,
+    copies: RefCell<Vec<PlannedCopy>>,
+}
+
+impl<'a, R: ReadsFiles> RecordingFiles<'a, R> {
+    pub fn new(inner: &'a mut R) -> RecordingFiles<'a, R> {
+        RecordingFiles{inner, writes: vec![], copies: RefCell::new(vec![])}
+    }
+
+    pub fn writes(&self) -> &Vec<PlannedWrite> {
+        &self.writes
+    }
+
+    pub fn copies(&self) -> Vec<PlannedCopy> {
+        self.copies.borrow().clone()
+    }
+
+    fn status(&mut self, filename: &str, contents: &[u8]) -> PlanStatus {
+        match self.inner.read_bytes(filename) {
+            Ok(existing) if existing == contents => PlanStatus::Unchanged,
+            Ok(_) => PlanStatus::Changed,
+            Err(_) => PlanStatus::New,
+        }
+    }
+
+    fn written(&self, filename: &str) -> Option<&PlannedWrite> {
+        self.writes.iter().rev().find(|ww| ww.output == filename)
+    }
+}
+
+impl<'a, R: ReadsFiles> ReadsFiles for RecordingFiles<'a, R> {
+    fn read(&mut self, filename: &str) -> Result<&str, FileError> {
+        if let Some(ii) = self.writes.iter().rposition(|ww| ww.output == filename) {
+            return std::str::from_utf8(&self.writes[ii].contents).map_err(|_| FileError::FileCantBeRead(filename.to_owned()));
+        }
+        self.inner.read(filename)
+    }
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        if let Some(ii) = self.writes.iter().rposition(|ww| ww.output == filename) {
+            return Ok(&self.writes[ii].contents);
+        }
+        self.inner.read_bytes(filename)
+    }
+    fn exists(&mut self, filename: &str) -> bool {
+        self.written(filename).is_some() || self.inner.exists(filename)
+    }
+    fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
+        self.write_bytes(filename, contents.as_bytes())
+    }
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        //writing the same file twice is judged against what's on disk, not the first write
+        let status = match self.written(filename) {
+            Some(earlier) if earlier.status == PlanStatus::New => PlanStatus::New,
+            _ => self.status(filename, contents),
+        };
+        self.writes.push(PlannedWrite{
+            output: filename.to_owned(),
+            input: None,
+            source: None,
+            index: None,
+            bytes: contents.len(),
+            status,
+            contents: contents.to_vec(),
+        });
+        Ok(())
+    }
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
+        self.inner.read_data(filename, format)
+    }
+    fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.copies.borrow_mut().push(PlannedCopy{from: from.to_owned(), to: to.to_owned()});
+        Ok(())
+    }
+    fn copy_file(&self, from: &str, to: &str, _mode: CopyMode) -> Result<(), FileError> {
+        self.copy_files(from, to)
+    }
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        self.inner.list_files(dir)
+    }
+    //pruning has its own dry run, so removals are just dropped
+    fn remove(&mut self, _filename: &str) -> Result<(), FileError> {
+        Ok(())
+    }
+}
+
+//everything a build would do, without having done any of it
+#[derive(Debug, Default)]
+pub struct BuildPlan {
+    pub writes: Vec<PlannedWrite>,
+    pub copies: Vec<PlannedCopy>,
+    pub errors: Vec<EntryError>,
+}
+
+impl BuildPlan {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for ww in &self.writes {
+            text.push_str(&format!("{:<9} {}", ww.status.to_string(), ww.output));
+            if let Some(input) = &ww.input {
+                text.push_str(&format!(" <- {}", input));
+            }
+            match (&ww.source, ww.index) {
+                (Some(source), Some(ii)) => text.push_str(&format!(" ({} entry {})", source, ii)),
+                (Some(source), None) => text.push_str(&format!(" ({})", source)),
+                _ => (),
+            }
+            text.push_str(&format!(", {} bytes\n", ww.bytes));
+        }
+        for cc in &self.copies {
+            text.push_str(&format!("{:<9} {} <- {}\n", "copy", cc.to, cc.from));
+        }
+        if !self.errors.is_empty() {
+            text.push_str(&error_summary(&self.errors));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let writes = self.writes.iter().map(|ww| {
+            let mut hh = new_yaml_map();
+            insert_value(&mut hh, "output", YamlValue::String(ww.output.to_owned()));
+            insert_value(&mut hh, "input", ww.input.to_owned().map_or(YamlValue::Null, YamlValue::String));
+            insert_value(&mut hh, "source", ww.source.as_ref().map_or(YamlValue::Null, |ss| YamlValue::String(ss.to_string())));
+            insert_value(&mut hh, "index", ww.index.map_or(YamlValue::Null, |ii| YamlValue::Integer(ii as i64)));
+            insert_value(&mut hh, "bytes", YamlValue::Integer(ww.bytes as i64));
+            insert_value(&mut hh, "status", YamlValue::String(ww.status.to_string()));
+            YamlValue::Hash(hh)
+        }).collect();
+        let copies = self.copies.iter().map(|cc| {
+            let mut hh = new_yaml_map();
+            insert_value(&mut hh, "from", YamlValue::String(cc.from.to_owned()));
+            insert_value(&mut hh, "to", YamlValue::String(cc.to.to_owned()));
+            YamlValue::Hash(hh)
+        }).collect();
+        let errors = self.errors.iter().map(|ee| {
+            let mut hh = new_yaml_map();
+            insert_value(&mut hh, "source", YamlValue::String(ee.source.to_string()));
+            insert_value(&mut hh, "index", ee.index.map_or(YamlValue::Null, |ii| YamlValue::Integer(ii as i64)));
+            insert_value(&mut hh, "error", YamlValue::String(format!("{:?}", ee.error)));
+            YamlValue::Hash(hh)
+        }).collect();
+        let mut plan = new_yaml_map();
+        insert_value(&mut plan, "writes", YamlValue::Array(writes));
+        insert_value(&mut plan, "copies", YamlValue::Array(copies));
+        insert_value(&mut plan, "errors", YamlValue::Array(errors));
+        to_json(&YamlValue::Hash(plan))
+    }
+}
+
+fn label_writes<R: ReadsFiles>(
+    io: &mut RecordingFiles<R>,
+    from: usize,
+    input: &str,
+    source: &ParamsSource,
+    index: Option<usize>
+) {
+    for ww in &mut io.writes[from..] {
+        ww.input = Some(input.to_owned());
+        ww.source = Some(source.to_owned());
+        ww.index = index;
+    }
+}
+
+//a page the cache skipped wrote nothing, but it's still part of the build
+fn note_skipped<R: ReadsFiles>(ctx: &BuildContext, io: &mut RecordingFiles<R>, from: usize, output: &str) {
+    let skipped = ctx.cache.as_ref().is_some_and(|cache| cache.lock().unwrap().skipped.last().is_some_and(|ss| ss == output));
+    if io.writes.len() != from || !skipped {
+        return;
+    }
+    let contents = io.inner.read_bytes(output).map(|bytes| bytes.to_vec()).unwrap_or_default();
+    io.writes.push(PlannedWrite{
+        output: output.to_owned(),
+        input: None,
+        source: None,
+        index: None,
+        bytes: contents.len(),
+        status: PlanStatus::Unchanged,
+        contents,
+    });
+}
+
+//runs the actions like run_actions, but against a RecordingFiles so nothing is written or copied.
+//the build cache is only consulted through a copy, and pages it would skip are planned as unchanged.
+//errors end up in the plan unless failing fast
+pub fn plan_actions(
+    actions: &[BuildAction],
+    ctx: &BuildContext,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles
+) -> Result<BuildPlan, BuildError> {
+    let ctx = &ctx.clone();
+    ctx.start_run();
+    let mut recording = RecordingFiles::new(io);
+    let mut errors: Vec<EntryError> = vec![];
+    for (ii, action) in actions.iter().enumerate() {
+        let result = plan_action(ii, action, ctx, pipes, &mut recording);
+        match result {
+            Ok(()) => (),
+            Err(error) if ctx.fail_fast => return Err(error),
+            Err(error) => push_action_error(&mut errors, ii, error),
+        }
+    }
+    let copies = recording.copies();
+    Ok(BuildPlan{writes: recording.writes, copies, errors})
+}
+
+fn plan_action<R: ReadsFiles>(
+    action_index: usize,
+    action: &BuildAction,
+    ctx: &BuildContext,
+    pipes: &PipeMap,
+    io: &mut RecordingFiles<R>
+) -> Result<(), BuildError> {
+    match action {
+        BuildAction::BuildPage{input, output, ..} => {
+            let from = io.writes.len();
+            action.run_in(ctx, pipes, io)?;
+            note_skipped(ctx, io, from, output);
+            label_writes(io, from, input, &ParamsSource::Action(action_index), None);
+            Ok(())
+        },
+        BuildAction::BuildMultiplePages{default_params, on} => {
+            let mut errors: Vec<EntryError> = vec![];
+            let (defaults, mapped) = build_multiple_pages_entries(ctx, default_params, on, pipes, io, &mut errors)?;
+            for ii in mapped {
+                let from = io.writes.len();
+                match build_page(ctx, &ii.2, &ii.3, &defaults, &ii.0, pipes, io) {
+                    Ok(()) => {
+                        note_skipped(ctx, io, from, &ii.3);
+                        label_writes(io, from, &ii.2, &ii.1, Some(ii.4));
+                    },
+                    Err(ee) => collect_page_error(ctx, &mut errors, ii.1, ii.4, ee)?,
+                }
+            }
+            many_or_ok(errors)
+        },
+        _ => action.run_in(ctx, pipes, io),
+    }
+}
@@ -0,0 +1,165 @@
+use crate::build::{BuildAction, BuildContext, BuildError, run_actions};
+use crate::io::{ReadsFiles, FileError, CopyMode};
+use crate::pipes::PipeMap;
+use crate::utils::clean_path;
+use crate::yaml::{YamlValue, YamlFileError, DataFormat};
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+//wraps a ReadsFiles and remembers every file written or copied through it
+pub struct TrackingOutputs<'a, R: ReadsFiles> {
+    inner: &'a mut R,
+    written: Vec<String>,
+    copies: RefCell<Vec<(String, String)>>,
+}
+
+impl<'a, R: ReadsFiles> TrackingOutputs<'a, R> {
+    pub fn new(inner: &'a mut R) -> TrackingOutputs<'a, R> {
+        TrackingOutputs{inner, written: vec![], copies: RefCell::new(vec![])}
+    }
+
+    //every file produced so far. a copied directory counts as every file under it, as it is now
+    pub fn outputs(&mut self) -> Vec<String> {
+        let mut outputs = self.written.clone();
+        for (from, to) in self.copies.borrow().iter() {
+            match self.inner.list_files(from) {
+                Ok(files) => outputs.extend(files.iter().map(|ff| {
+                    let relative = clean_path(ff);
+                    let relative = relative.strip_prefix(&clean_path(from)).unwrap_or(&relative);
+                    format!("{}/{}", to.trim_end_matches('/'), relative.trim_start_matches('/'))
+                })),
+                Err(_) => outputs.push(to.to_owned()),
+            }
+        }
+        outputs
+    }
+}
+
+impl<'a, R: ReadsFiles> ReadsFiles for TrackingOutputs<'a, R> {
+    fn read(&mut self, filename: &str) -> Result<&str, FileError> {
+        self.inner.read(filename)
+    }
+    fn read_bytes(&mut self, filename: &str) -> Result<&[u8], FileError> {
+        self.inner.read_bytes(filename)
+    }
+    fn exists(&mut self, filename: &str) -> bool {
+        self.inner.exists(filename)
+    }
+    fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
+        self.written.push(filename.to_owned());
+        self.inner.write(filename, contents)
+    }
+    fn write_bytes(&mut self, filename: &str, contents: &[u8]) -> Result<(), FileError> {
+        self.written.push(filename.to_owned());
+        self.inner.write_bytes(filename, contents)
+    }
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
+        self.inner.read_data(filename, format)
+    }
+    fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.copies.borrow_mut().push((from.to_owned(), to.to_owned()));
+        self.inner.copy_files(from, to)
+    }
+    fn copy_file(&self, from: &str, to: &str, mode: CopyMode) -> Result<(), FileError> {
+        self.copies.borrow_mut().push((from.to_owned(), to.to_owned()));
+        self.inner.copy_file(from, to, mode)
+    }
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        self.inner.list_files(dir)
+    }
+    fn remove(&mut self, filename: &str) -> Result<(), FileError> {
+        self.inner.remove(filename)
+    }
+}
+
+pub struct PruneOptions {
+    pub output_root: String,
+    //globs relative to the output root. a file is kept if a glob matches it or any directory above it
+    pub protect: Vec<String>,
+    //list what would be deleted without deleting it
+    pub dry_run: bool,
+}
+
+impl PruneOptions {
+    pub fn new(output_root: &str) -> PruneOptions {
+        PruneOptions{
+            output_root: output_root.to_owned(),
+            protect: vec![".git".to_owned(), "CNAME".to_owned()],
+            dry_run: false,
+        }
+    }
+
+    //a bad glob is an error rather than ignored, since ignoring it would delete what it protects
+    fn protect_patterns(&self) -> Result<Vec<glob::Pattern>, BuildError> {
+        self.protect.iter()
+            .map(|pp| glob::Pattern::new(pp).map_err(|ee| BuildError::BadGlob(pp.to_owned(), ee.to_string())))
+            .collect()
+    }
+}
+
+//`*` doesn't cross a slash, so `*.html` only protects pages at the top of the output root
+fn is_protected(patterns: &[glob::Pattern], relative: &str) -> bool {
+    let options = glob::MatchOptions{require_literal_separator: true, ..glob::MatchOptions::new()};
+    let mut path = relative;
+    loop {
+        if patterns.iter().any(|pp| pp.matches_with(path, options)) {
+            return true;
+        }
+        match path.rfind('/') {
+            Some(ii) => path = &path[..ii],
+            None => return false,
+        }
+    }
+}
+
+//cleaned up and made absolute against the working directory, without touching the filesystem, so
+//`out/a.html`, `./out/a.html` and `/site/out/a.html` all compare equal
+fn absolute(path: &str) -> String {
+    if path.starts_with('/') || std::path::Path::new(path).is_absolute() {
+        return clean_path(path);
+    }
+    let cwd = std::env::current_dir().map(|dir| dir.to_string_lossy().into_owned()).unwrap_or_default();
+    clean_path(&format!("{}/{}", cwd, path))
+}
+
+//deletes every file under the output root that isn't one of the outputs or protected, returning
+//them sorted. in a dry run they're only returned
+pub fn prune(outputs: &[String], options: &PruneOptions, io: &mut impl ReadsFiles) -> Result<Vec<String>, BuildError> {
+    let patterns = options.protect_patterns()?;
+    let outputs: HashSet<String> = outputs.iter().map(|oo| absolute(oo)).collect();
+    let root = absolute(&options.output_root);
+    let mut stale = vec![];
+    for file in io.list_files(&options.output_root).map_err(BuildError::FileError)? {
+        let file_absolute = absolute(&file);
+        let relative = file_absolute.strip_prefix(&root).unwrap_or(&file_absolute).trim_start_matches('/');
+        if !outputs.contains(&file_absolute) && !is_protected(&patterns, relative) {
+            stale.push(file);
+        }
+    }
+    if !options.dry_run {
+        for file in &stale {
+            io.remove(file).map_err(BuildError::FileError)?;
+        }
+    }
+    Ok(stale)
+}
+
+//runs the actions and then prunes whatever they didn't produce. pages the build cache skipped still
+//count as produced. nothing is pruned if the build had errors, since the pages that failed would be
+//deleted along with the stale ones
+pub fn build_and_prune(
+    actions: &[BuildAction],
+    ctx: &BuildContext,
+    pipes: &PipeMap,
+    io: &mut impl ReadsFiles,
+    options: &PruneOptions
+) -> Result<Vec<String>, BuildError> {
+    options.protect_patterns()?;
+    let mut tracking = TrackingOutputs::new(io);
+    run_actions(actions, ctx, pipes, &mut tracking)?;
+    let mut outputs = tracking.outputs();
+    if let Some(cache) = &ctx.cache {
+        outputs.extend(cache.lock().unwrap().skipped.iter().cloned());
+    }
+    prune(&outputs, options, io)
+}
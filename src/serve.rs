@@ -0,0 +1,204 @@
+use crate::build::{BuildAction, BuildContext, BuildError};
+use crate::io::FileCache;
+use crate::pipes::PipeMap;
+use crate::utils::mime_type;
+use crate::watch::{WatchOptions, watch_with};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const LIVE_RELOAD_PATH: &str = "/__livereload";
+
+//polls the server for the build number and reloads when it goes up
+const LIVE_RELOAD_SCRIPT: &str = "<script>(function(){var seen=null;setInterval(function(){\
+fetch('/__livereload').then(function(r){return r.text();}).then(function(v){\
+if(seen!==null&&v!==seen){location.reload();}seen=v;}).catch(function(){});},500);})();</script>";
+
+pub struct ServeOptions {
+    pub output_dir: String,
+    pub port: u16,
+    pub watch: WatchOptions,
+}
+
+//what the server knows about the builds. `version` goes up after every build
+#[derive(Debug, Default)]
+pub struct ServeState {
+    pub version: u64,
+    pub errors: Vec<String>,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+fn content_type(path: &Path) -> &'static str {
+    mime_type(&path.to_string_lossy())
+}
+
+//the script goes just before the closing body tag, or at the end if there isn't one
+pub fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(ii) => format!("{}{}{}", &html[..ii], LIVE_RELOAD_SCRIPT, &html[ii..]),
+        None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+    }
+}
+
+fn escape_html(strr: &str) -> String {
+    strr.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn error_page(errors: &[String]) -> String {
+    let items: Vec<String> = errors.iter().map(|ee| format!("<pre>{}</pre>", escape_html(ee))).collect();
+    inject_live_reload(&format!(
+        "<!DOCTYPE html><html><head><title>Build failed</title></head>\
+<body style=\"background:#300;color:#fdd;font-family:monospace;padding:2em\">\
+<h1>Build failed</h1>{}</body></html>",
+        items.join("")
+    ))
+}
+
+//`%20` and friends back to the bytes they stand for. None if that isn't utf-8 or has a nul in it
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut ii = 0;
+    while ii < bytes.len() {
+        let hex = bytes.get(ii + 1..ii + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[ii], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                ii += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                ii += 1;
+            },
+        }
+    }
+    String::from_utf8(decoded).ok().filter(|decoded| !decoded.contains('\0'))
+}
+
+//maps a request path onto a file under the root, refusing anything that would leave it. the path is
+//decoded first, so an encoded `..` is refused too
+pub fn resolve_path(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let path = percent_decode(request_path.split(['?', '#']).next().unwrap_or(""))?;
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    if resolved.is_dir() {
+        resolved.push("index.html");
+    }
+    Some(resolved)
+}
+
+pub fn handle_request(method: &str, request_path: &str, root: &Path, state: &ServeState) -> Response {
+    if method != "GET" && method != "HEAD" {
+        return Response{status: 405, content_type: "text/plain; charset=utf-8", body: b"method not allowed".to_vec()};
+    }
+    if request_path == LIVE_RELOAD_PATH {
+        return Response{status: 200, content_type: "text/plain; charset=utf-8", body: state.version.to_string().into_bytes()};
+    }
+    let path = match resolve_path(root, request_path) {
+        Some(path) => path,
+        None => return Response{status: 403, content_type: "text/plain; charset=utf-8", body: b"forbidden".to_vec()},
+    };
+    let content_type = content_type(&path);
+    let is_html = content_type.starts_with("text/html");
+    if !state.errors.is_empty() && (is_html || path.extension().is_none()) {
+        return Response{status: 500, content_type: "text/html; charset=utf-8", body: error_page(&state.errors).into_bytes()};
+    }
+    match fs::read(&path) {
+        Err(_) => Response{status: 404, content_type: "text/plain; charset=utf-8", body: b"not found".to_vec()},
+        Ok(body) if is_html => Response{
+            status: 200,
+            content_type,
+            body: inject_live_reload(&String::from_utf8_lossy(&body)).into_bytes(),
+        },
+        Ok(body) => Response{status: 200, content_type, body},
+    }
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+fn handle_connection(stream: TcpStream, root: &Path, state: &Mutex<ServeState>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    //the headers aren't needed, but they have to be read before responding
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let request_path = parts.next().unwrap_or("/");
+    let response = handle_request(method, request_path, root, &state.lock().unwrap());
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status, status_text(response.status), response.content_type, response.body.len()
+    )?;
+    if method != "HEAD" {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+//serves the output directory on localhost only, in the background
+pub fn start_server(options: &ServeOptions, state: Arc<Mutex<ServeState>>) -> std::io::Result<TcpListener> {
+    let listener = TcpListener::bind(("127.0.0.1", options.port))?;
+    let accepting = listener.try_clone()?;
+    let root = PathBuf::from(&options.output_dir);
+    thread::spawn(move || {
+        for stream in accepting.incoming().flatten() {
+            let root = root.clone();
+            let state = state.clone();
+            thread::spawn(move || {
+                if let Err(ee) = handle_connection(stream, &root, &state) {
+                    eprintln!("couldn't respond: {}", ee);
+                }
+            });
+        }
+    });
+    Ok(listener)
+}
+
+//builds, serves the output and rebuilds on changes, reloading any open pages after every build
+pub fn serve(
+    actions: &[BuildAction],
+    ctx: &mut BuildContext,
+    pipes: &PipeMap,
+    io: &mut FileCache,
+    options: &ServeOptions
+) -> std::io::Result<()> {
+    let state = Arc::new(Mutex::new(ServeState::default()));
+    let listener = start_server(options, state.clone())?;
+    println!("serving {} at http://{}", options.output_dir, listener.local_addr()?);
+    watch_with(actions, ctx, pipes, io, &options.watch, |errors: &[(usize, BuildError)]| {
+        let mut state = state.lock().unwrap();
+        state.errors = errors.iter().map(|(ii, ee)| format!("action {}: {:?}", ii, ee)).collect();
+        state.version += 1;
+    })
+}
@@ -1,23 +1,31 @@
 use crate::yaml::{
-    lookup_value,
+    lookup_accesses,
     tostr,
     YamlMap,
     YamlValue,
     to_iterable,
-    insert_value,
     YamlFileError,
+    DataFormat,
+    CompoundFormat,
 };
 use crate::parsers::parse_template_string;
+use crate::assets::AssetManifest;
 use crate::io::{ReadsFiles, FileError};
-use crate::utils::{map_m};
+use crate::utils::{hash_str};
 use crate::pipes::{
     Pipe, PipeMap, execute_pipe
 };
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum TemplateElement {
     PlainText(String),
     Replace { value: TemplateValue, pipe: Vec<Pipe> },
+    //a quoted string, as in `{{ "css/app.css" | asset }}`
+    Literal { text: String, pipe: Vec<Pipe> },
     File { snippet: bool, filename: String, pipe: Vec<Pipe> },
     FileAt { snippet: bool, value: TemplateValue, pipe: Vec<Pipe> },
     IfExists {
@@ -30,6 +38,9 @@ pub enum TemplateElement {
         values: Vec<TemplateValue>,
         filenames: Vec<String>,
         files_at: Vec<TemplateValue>,
+        //forces the format of the files, otherwise it comes from their extensions
+        file_format: Option<DataFormat>,
+        file_at_format: Option<DataFormat>,
         main: Vec<TemplateElement>,
         separator: Vec<TemplateElement>
     },
@@ -47,6 +58,130 @@ pub enum TemplateValueAccess {
     Index(usize)
 }
 
+impl fmt::Display for TemplateValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.base)?;
+        for access in &self.accesses {
+            match access {
+                TemplateValueAccess::Field(ff) => write!(f, ".{}", ff)?,
+                TemplateValueAccess::Index(ii) => write!(f, "[{}]", ii)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+//settings for a single render
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    pub compound: CompoundFormat,
+    pub sources: SourcePaths,
+    pub templates: TemplateStore,
+    //filled in by fingerprinted copies, for the `asset` pipe
+    pub assets: AssetManifest,
+}
+
+pub type ParsedTemplate = Arc<Vec<TemplateElement>>;
+
+//parsed templates, keyed by where they came from along with a hash of their contents, so a layout
+//used by every page is only parsed once. clones share the same store, and it's safe to use across
+//threads. a template that changes replaces the old parse under the same name. unnamed templates, like
+//output mappings, are kept under their hash
+#[derive(Debug, Clone, Default)]
+pub struct TemplateStore {
+    parsed: Arc<Mutex<HashMap<String, (u64, ParsedTemplate)>>>,
+    parses: Arc<AtomicUsize>,
+}
+
+impl TemplateStore {
+    pub fn new() -> TemplateStore {
+        TemplateStore::default()
+    }
+
+    //the parse error is returned as its message and isn't kept
+    pub fn parse(&self, name: &str, source: &str) -> Result<ParsedTemplate, String> {
+        let hash = hash_str(source);
+        let key = match name {
+            "" => format!("#{:016x}", hash),
+            name => name.to_owned(),
+        };
+        if let Some((_, elements)) = self.parsed.lock().unwrap().get(&key).filter(|(hh, _)| *hh == hash) {
+            return Ok(elements.clone());
+        }
+        //parsed outside the lock so threads don't wait on each other's parses
+        let elements = Arc::new(parse_template_string(source).map_err(|ee| ee.to_string())?);
+        self.parses.fetch_add(1, Ordering::Relaxed);
+        self.parsed.lock().unwrap().insert(key, (hash, elements.clone()));
+        Ok(elements)
+    }
+
+    //how many times a template has actually been parsed
+    pub fn parses(&self) -> usize {
+        self.parses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.parsed.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+//where pages, snippets, included files, layouts and `in-file` data are looked for. each search path is
+//tried in order under the root, so a site listed before its theme overrides any of the theme's files
+#[derive(Debug, Clone)]
+pub struct SourcePaths {
+    pub root: String,
+    pub snippet_dir: String,
+    pub search: Vec<String>,
+}
+
+impl Default for SourcePaths {
+    fn default() -> SourcePaths {
+        SourcePaths{root: "".to_owned(), snippet_dir: "resources/snippets".to_owned(), search: vec![]}
+    }
+}
+
+fn join_path(parts: &[&str]) -> String {
+    let parts: Vec<&str> = parts.iter().map(|pp| pp.trim_end_matches('/')).filter(|pp| !pp.is_empty()).collect();
+    parts.join("/")
+}
+
+impl SourcePaths {
+    //every place the file could be, most preferred first
+    pub fn candidates(&self, filename: &str, snippet: bool) -> Vec<String> {
+        if filename.starts_with('/') {
+            return vec![filename.to_owned()];
+        }
+        let snippet_dir = if snippet {self.snippet_dir.as_str()} else {""};
+        if self.search.is_empty() {
+            return vec![join_path(&[&self.root, snippet_dir, filename])];
+        }
+        self.search.iter().map(|search| join_path(&[&self.root, search, snippet_dir, filename])).collect()
+    }
+
+    //the first candidate that can be read. when none can, the first, so errors name a sensible path
+    pub fn resolve(&self, filename: &str, snippet: bool, io: &mut impl ReadsFiles) -> String {
+        let candidates = self.candidates(filename, snippet);
+        for candidate in &candidates {
+            if io.read(candidate).is_ok() {
+                return candidate.to_owned();
+            }
+        }
+        candidates[0].to_owned()
+    }
+
+    //like resolve, but a page that isn't under the root or any search path is read as it was given, so
+    //inputs named from the working directory still work
+    pub fn resolve_page(&self, filename: &str, io: &mut impl ReadsFiles) -> String {
+        self.candidates(filename, false).into_iter()
+            .find(|candidate| io.read(candidate).is_ok())
+            .unwrap_or_else(|| filename.to_owned())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum TemplateError {
     KeyNotPresent(String),
@@ -61,107 +196,176 @@ pub enum TemplateError {
     ForOnUnindexable(String),
     PipeMissing(String),
     PipeExecutionError(String),
+    NonScalarValue(String),
+    WriteError(String),
 }
+//the params as seen from inside for loops. each loop's variable sits on top of the scope around it,
+//so nothing has to be copied to add it
+pub enum Scope<'a> {
+    Root(&'a YamlMap),
+    Layer { name: &'a str, value: &'a YamlValue, parent: &'a Scope<'a> },
+}
+
+impl<'a> Scope<'a> {
+    pub fn get(&self, key: &str) -> Option<&'a YamlValue> {
+        match self {
+            Scope::Root(params) => params.get(&YamlValue::String(key.to_owned())),
+            Scope::Layer{name, value, parent} => if *name == key {Some(value)} else {parent.get(key)},
+        }
+    }
+
+    //the value with its fields and indexes followed
+    pub fn lookup(&self, value: &'a TemplateValue) -> Result<&'a YamlValue, TemplateError> {
+        match self.get(&value.base) {
+            Some(base) => lookup_accesses(value, base),
+            None => Err(TemplateError::KeyNotPresent(value.base.to_owned())),
+        }
+    }
+}
+
 impl TemplateElement {
-    fn render<'a>(&'a self, params: &'a YamlMap, pipes: &'a PipeMap, io: &mut impl ReadsFiles) -> Result<String, TemplateError> {
+    fn render_to<'a>(
+        &'a self,
+        out: &mut impl fmt::Write,
+        scope: &Scope<'a>,
+        pipes: &'a PipeMap,
+        options: &RenderOptions,
+        io: &mut impl ReadsFiles
+    ) -> Result<(), TemplateError> {
         match self {
-            TemplateElement::PlainText(text) => Ok(text.clone()),
+            TemplateElement::PlainText(text) => write_str(out, text),
             TemplateElement::Replace{value, pipe} => {
-                let lookup = lookup_value(value, params)?;
-                let mut current = lookup.clone();
-                for ii in pipe {
-                    current = execute_pipe(&current, &ii.name, pipes, io)?;
-                }
-                tostr(&current)
+                let lookup = scope.lookup(value)?;
+                let rendered = if pipe.is_empty() {
+                    tostr(lookup, options.compound)
+                } else {
+                    tostr(&run_pipes(lookup.clone(), pipe, pipes, options, io)?, options.compound)
+                };
+                let rendered = rendered.map_err(|ee| match ee {
+                    TemplateError::NonScalarValue(..) => TemplateError::NonScalarValue(value.to_string()),
+                    _ => ee
+                })?;
+                write_str(out, &rendered)
+            },
+            TemplateElement::Literal{text, pipe} => {
+                let piped = run_pipes(YamlValue::String(text.to_owned()), pipe, pipes, options, io)?;
+                write_str(out, &tostr(&piped, options.compound)?)
             },
             TemplateElement::File{snippet, filename, pipe} => {
-                let real_filename = format!("{}{}", if *snippet {"resources/snippets/"} else {""}, filename);
+                let real_filename = options.sources.resolve(filename, *snippet, io);
                 match io.read(&real_filename) {
-                    Ok(strr) => Ok(strr.to_owned()),
+                    Ok(strr) => write_str(out, strr),
                     Err(ee) => Err(TemplateError::FileError(ee))
                 }
             },
             TemplateElement::FileAt{snippet, value, pipe} => {
-                let lookup = lookup_value(value, params)?;
-                let filename = tostr(lookup)?;
-                let real_filename = format!("{}{}", if *snippet {"resources/snippets/"} else {""}, filename);
+                let lookup = scope.lookup(value)?;
+                let filename = tostr(lookup, CompoundFormat::Strict)?;
+                let real_filename = options.sources.resolve(&filename, *snippet, io);
                 match io.read(&real_filename) {
-                    Ok(strr) => Ok(strr.to_owned()),
+                    Ok(strr) => write_str(out, strr),
                     Err(ee) => Err(TemplateError::FileError(ee))
                 }
             }
             TemplateElement::IfExists{value, when_true, when_false} => {
-                let lookup = lookup_value(value, params);
+                let lookup = scope.lookup(value);
                 match lookup {
-                    Ok(..) => render_elements(when_true, params, pipes, io),
+                    Ok(..) => render_scoped(out, when_true, scope, pipes, options, io),
                     Err(ee) => match ee {
                         TemplateError::KeyNotPresent(..) |
                         TemplateError::FieldNotPresent(..) |
-                        TemplateError::IndexOOB(..) => render_elements(when_false, params, pipes, io),
+                        TemplateError::IndexOOB(..) => render_scoped(out, when_false, scope, pipes, options, io),
                         _ => Err(ee)
                     }
                 }
             }
-            TemplateElement::For{name, values, filenames, files_at, main, separator, ..} => {
-                let over = for_make_iterable(params, values, filenames, files_at, io)?;
-                let mut mapped: Vec<String> = map_m(over, |ii| {
-                    let mut new_params = params.clone();
-                    insert_value(&mut new_params, &name, ii.clone());
-                    render_elements(main, &new_params, pipes, io)
-                })?;
-                let sep = render_elements(separator, params, pipes, io)?;
-                Ok(mapped.join(&sep))
+            TemplateElement::For{name, values, filenames, files_at, file_format, file_at_format, main, separator} => {
+                let mut over = Vec::new();
+                for value in values {
+                    over.append(&mut to_iterable(scope.lookup(value)?)?);
+                }
+                for filename in filenames {
+                    over.append(&mut for_file_entries(filename, *file_format, options, io)?);
+                }
+                for fileat in files_at {
+                    let filename = tostr(scope.lookup(fileat)?, CompoundFormat::Strict)?;
+                    over.append(&mut for_file_entries(&filename, *file_at_format, options, io)?);
+                }
+                let mut sep = String::new();
+                render_scoped(&mut sep, separator, scope, pipes, options, io)?;
+                for (ii, value) in over.iter().enumerate() {
+                    if ii > 0 {
+                        write_str(out, &sep)?;
+                    }
+                    let inner = Scope::Layer{name, value, parent: scope};
+                    render_scoped(out, main, &inner, pipes, options, io)?;
+                }
+                Ok(())
             }
         }
     }
 }
 
-fn for_make_iterable(
-    params: & YamlMap,
-    values: &Vec<TemplateValue>,
-    filenames: &Vec<String>,
-    files_at: &Vec<TemplateValue>,
+fn write_str(out: &mut impl fmt::Write, strr: &str) -> Result<(), TemplateError> {
+    out.write_str(strr).map_err(|_| TemplateError::WriteError("couldn't write the output".to_owned()))
+}
+
+fn run_pipes(
+    value: YamlValue,
+    pipe: &[Pipe],
+    pipes: &PipeMap,
+    options: &RenderOptions,
     io: &mut impl ReadsFiles
-) -> Result<Vec<YamlValue>, TemplateError> {
-    let mut entries = Vec::new();
-    for value in values {
-        let lookup = lookup_value(&value, params)?;
-        let mut as_vec = to_iterable(lookup)?;
-        entries.append(&mut as_vec);
-    }
-    for filename in filenames {
-        let lookup = io.read_yaml(filename)
-            .map_err(|xx| TemplateError::YamlFileError(xx))?;
-        let mut as_vec = to_iterable(lookup)?;
-        entries.append(&mut as_vec);
+) -> Result<YamlValue, TemplateError> {
+    let mut current = value;
+    for ii in pipe {
+        current = execute_pipe(&current, &ii.name, pipes, options, io)?;
     }
-    for fileat in files_at {
-        let lookup = lookup_value(&fileat, params)?;
-        let filename = tostr(lookup)?;
-        let file = io.read_yaml(&filename)
-            .map_err(|xx| TemplateError::YamlFileError(xx))?;
-        let mut as_vec = to_iterable(file)?;
-        entries.append(&mut as_vec);
+    Ok(current)
+}
+
+//the entries of a data file that a for loop runs over
+fn for_file_entries(
+    filename: &str,
+    forced_format: Option<DataFormat>,
+    options: &RenderOptions,
+    io: &mut impl ReadsFiles
+) -> Result<Vec<YamlValue>, TemplateError> {
+    let format = forced_format.unwrap_or_else(|| DataFormat::from_filename(filename));
+    let resolved = options.sources.resolve(filename, false, io);
+    let file = io.read_data(&resolved, format)
+        .map_err(TemplateError::YamlFileError)?;
+    match file {
+        //a stream holding just the one document
+        YamlValue::Hash(..) => Ok(vec![file.to_owned()]),
+        _ => to_iterable(file),
     }
-    Ok(entries)
 }
 
 pub fn render_elements<'a>(
     elements: &'a Vec<TemplateElement>,
     params: &'a YamlMap,
     pipes: &'a PipeMap,
+    options: &RenderOptions,
     io: &mut impl ReadsFiles
 ) -> Result<String, TemplateError> {
-    elements.iter().try_fold("".to_owned(), |acc, ii| {
-        match ii.render(params, pipes, io) {
-            err @ Err(..) => err,
-            Ok(result) => {
-                let mut string = acc.to_owned();
-                string.push_str(&result);
-                Ok(string)
-            }
-        }
-    })
+    let mut out = String::new();
+    render_scoped(&mut out, elements, &Scope::Root(params), pipes, options, io)?;
+    Ok(out)
+}
+
+pub fn render_scoped<'a>(
+    out: &mut impl fmt::Write,
+    elements: &'a [TemplateElement],
+    scope: &Scope<'a>,
+    pipes: &'a PipeMap,
+    options: &RenderOptions,
+    io: &mut impl ReadsFiles
+) -> Result<(), TemplateError> {
+    for element in elements {
+        element.render_to(out, scope, pipes, options, io)?;
+    }
+    Ok(())
 }
 
 pub fn render<'a>(
@@ -170,8 +374,75 @@ pub fn render<'a>(
     pipes: &'a PipeMap,
     io: &mut impl ReadsFiles
 ) -> Result<String, TemplateError> {
-    match parse_template_string(input) {
-        Err(ee) => Err(TemplateError::ParseError(ee.to_string())),
-        Ok(elements) => render_elements(&elements, params, pipes, io)
+    render_with(input, params, pipes, &RenderOptions::default(), io)
+}
+
+pub fn render_with<'a>(
+    input: &'a str,
+    params: &'a YamlMap,
+    pipes: &'a PipeMap,
+    options: &RenderOptions,
+    io: &mut impl ReadsFiles
+) -> Result<String, TemplateError> {
+    render_named("", input, params, pipes, options, io)
+}
+
+//render_with, for a template that came from a file. the name lets the store drop the old parse when
+//the file changes
+pub fn render_named<'a>(
+    name: &str,
+    input: &'a str,
+    params: &'a YamlMap,
+    pipes: &'a PipeMap,
+    options: &RenderOptions,
+    io: &mut impl ReadsFiles
+) -> Result<String, TemplateError> {
+    let elements = options.templates.parse(name, input).map_err(TemplateError::ParseError)?;
+    render_elements(&elements, params, pipes, options, io)
+}
+
+//renders straight into the output instead of building up a string. if it fails part way, whatever
+//was rendered before the error has already been written
+pub fn render_to<'a>(
+    out: &mut impl fmt::Write,
+    input: &'a str,
+    params: &'a YamlMap,
+    pipes: &'a PipeMap,
+    options: &RenderOptions,
+    io: &mut impl ReadsFiles
+) -> Result<(), TemplateError> {
+    let elements = options.templates.parse("", input).map_err(TemplateError::ParseError)?;
+    render_scoped(out, &elements, &Scope::Root(params), pipes, options, io)
+}
+
+//lets a std::io::Write be rendered into, keeping the error that stopped it
+struct IoWriter<'a, W: std::io::Write> {
+    inner: &'a mut W,
+    error: Option<std::io::Error>,
+}
+
+impl<'a, W: std::io::Write> fmt::Write for IoWriter<'a, W> {
+    fn write_str(&mut self, strr: &str) -> fmt::Result {
+        self.inner.write_all(strr.as_bytes()).map_err(|ee| {
+            self.error = Some(ee);
+            fmt::Error
+        })
+    }
+}
+
+//render_to, for files, sockets and the like
+pub fn render_to_io<'a>(
+    out: &mut impl std::io::Write,
+    input: &'a str,
+    params: &'a YamlMap,
+    pipes: &'a PipeMap,
+    options: &RenderOptions,
+    io: &mut impl ReadsFiles
+) -> Result<(), TemplateError> {
+    let mut writer = IoWriter{inner: out, error: None};
+    let result = render_to(&mut writer, input, params, pipes, options, io);
+    match writer.error {
+        Some(ee) => Err(TemplateError::WriteError(ee.to_string())),
+        None => result,
     }
 }
@@ -1,8 +1,16 @@
-use crate::io::{ReadsFiles, FileError};
-use crate::build::{BuildAction};
-use crate::yaml::{YamlMap};
-use crate::tests::common::{TestFileCache, setup_io, setup_pipes};
-use yaml_rust2::{yaml::{Hash, Yaml}, YamlLoader};
+use crate::io::{FileCache, ReadsFiles, FileError};
+use crate::build::{
+    BuildAction, BuildMultiplePages, BuildError, BuildContext, EntryError, ParamsSource, run_actions, error_summary,
+};
+use crate::template::{SourcePaths, TemplateError, TemplateStore};
+use crate::data::load_data_dir;
+use crate::incremental::BuildCache;
+use crate::memory::MemoryFs;
+use crate::pipes::add_file_pipes;
+use std::sync::Mutex;
+use crate::yaml::{DataFormat, YamlFileError, load_data};
+use crate::tests::common::{TestFileCache, params, setup_io, setup_pipes, temp_dir};
+use yaml_rust2::yaml::Yaml;
 
 fn runs(
     action: BuildAction,
@@ -11,13 +19,6 @@ fn runs(
     assert_eq!(Ok(()), action.run(&setup_pipes(), io));
 }
 
-fn params(strr: &str) -> YamlMap {
-    let parsed = YamlLoader::load_from_str(strr).unwrap();
-    let doc = &parsed[0];
-    let pp: Hash = doc.as_hash().expect("not a hash map?").clone();
-    pp
-}
-
 #[test]
 fn Build_single_page() {
     let mut io = setup_io();
@@ -25,3 +26,416 @@ fn Build_single_page() {
     assert_eq!(1, io.written.len());
     io.assert_written("out.txt", "foo test yay");
 }
+
+#[test]
+fn build_page_with_front_matter() {
+    let mut io = setup_io();
+    runs(BuildAction::BuildPage{output: "out.txt".to_string(), input: "front01.txt".to_string(), params: params("{}")}, &mut io);
+    io.assert_written("out.txt", "hello front");
+}
+
+#[test]
+fn build_page_params_beat_front_matter() {
+    let mut io = setup_io();
+    runs(BuildAction::BuildPage{output: "out.txt".to_string(), input: "front01.txt".to_string(), params: params("bar: param")}, &mut io);
+    io.assert_written("out.txt", "hello param");
+}
+
+#[test]
+fn build_page_with_layouts() {
+    let mut io = setup_io();
+    runs(BuildAction::BuildPage{output: "out.txt".to_string(), input: "front02.txt".to_string(), params: params("{}")}, &mut io);
+    io.assert_written("out.txt", "<[inner: body inner] fromlayout>");
+}
+
+#[test]
+fn build_page_layout_cycle() {
+    let mut io = setup_io();
+    let action = BuildAction::BuildPage{output: "out.txt".to_string(), input: "layoutcycle.txt".to_string(), params: params("{}")};
+    assert_eq!(Err(BuildError::LayoutCycle("layoutcycle.txt".to_string())), action.run(&setup_pipes(), &mut io));
+}
+
+#[test]
+fn build_multiple_pages_front_matter_precedence() {
+    let mut io = setup_io();
+    runs(BuildAction::BuildMultiplePages{
+        default_params: params("input: front01.txt\ntitle: default\nbar: default"),
+        on: vec![BuildMultiplePages{
+            files: vec!["multiple01.yaml".to_string()],
+            params: vec![params("name: three\nbar: entry")],
+            mapping: params("output: \"{{name}}.txt\""),
+        }],
+    }, &mut io);
+    assert_eq!(3, io.written.len());
+    io.assert_written("one.txt", "hello front");
+    io.assert_written("two.txt", "entry front");
+    io.assert_written("three.txt", "hello entry");
+}
+
+#[test]
+fn build_multiple_pages_without_output() {
+    let mut io = setup_io();
+    let action = BuildAction::BuildMultiplePages{
+        default_params: params("input: base01.txt"),
+        on: vec![BuildMultiplePages{files: vec![], params: vec![params("bar: a")], mapping: params("{}")}],
+    };
+    let ctx = BuildContext{fail_fast: true, ..BuildContext::default()};
+    assert_eq!(Err(BuildError::BMOutputNotSpecified("None".to_string())), action.run_in(&ctx, &setup_pipes(), &mut io));
+}
+
+#[test]
+fn load_data_dir_nests_directories() {
+    let mut io = setup_io();
+    let data = load_data_dir("data", &mut io).unwrap();
+    assert_eq!(data, params("authors: {alice: {name: Alice}}\nconfig: {name: epic}\nnav: [home, about]"));
+}
+
+#[test]
+fn load_data_dir_matches_listings_however_the_dir_is_named() {
+    let mut io = MemoryFs::from_files([("data/nav.yaml", "[home]"), ("data/données/é.yaml", "{a: 1}")]);
+    let expected = params("données: {é: {a: 1}}\nnav: [home]");
+    for dir in ["data", "./data", "data/", "./data//"] {
+        assert_eq!(Ok(expected.clone()), load_data_dir(dir, &mut io));
+    }
+}
+
+#[test]
+fn load_data_dir_rejects_files_sharing_a_stem() {
+    let mut io = MemoryFs::from_files([("data/a.yaml", "{x: 1}"), ("data/a.json", "{\"y\": 2}")]);
+    assert_eq!(Err(BuildError::DataKeyConflict("data/a.yaml".to_owned())), load_data_dir("data", &mut io));
+    let mut io = MemoryFs::from_files([("data/a.yaml", "{x: 1}"), ("data/a/b.yaml", "{y: 2}")]);
+    assert_eq!(Ok(params("a: {x: 1, b: {y: 2}}")), load_data_dir("data", &mut io));
+}
+
+#[test]
+fn site_data_in_every_render() {
+    let mut io = setup_io();
+    let ctx = BuildContext::with_data_dir("data", &mut io).unwrap();
+    let action = BuildAction::BuildPage{output: "out.txt".to_string(), input: "site01.txt".to_string(), params: params("{}")};
+    assert_eq!(Ok(()), action.run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("out.txt", "epic about Alice");
+}
+
+#[test]
+fn load_toml_data() {
+    let mut io = setup_io();
+    let loaded = io.read_yaml("config.toml").unwrap();
+    assert_eq!(&Yaml::Hash(params("title: t\nitems: [{n: 1}, {n: 2.5}]")), loaded);
+}
+
+#[test]
+fn load_invalid_toml_data() {
+    assert!(matches!(load_data("title = ", DataFormat::Toml), Err(YamlFileError::Toml(..))));
+}
+
+#[test]
+fn build_multiple_pages_from_csv() {
+    let mut io = setup_io();
+    runs(BuildAction::BuildMultiplePages{
+        default_params: params("input: base01.txt"),
+        on: vec![BuildMultiplePages{
+            files: vec!["people.csv".to_string()],
+            params: vec![],
+            mapping: params("output: \"{{name}}.txt\"\nbar: \"{{age}}\""),
+        }],
+    }, &mut io);
+    io.assert_written("ann.txt", "foo 30 yay");
+    io.assert_written("bo, jr.txt", "foo 4 yay");
+}
+
+#[test]
+fn build_multiple_pages_from_yaml_streams() {
+    let mut io = setup_io();
+    runs(BuildAction::BuildMultiplePages{
+        default_params: params("input: base01.txt"),
+        on: vec![BuildMultiplePages{
+            files: vec!["stream.yaml".to_string(), "single.yaml".to_string()],
+            params: vec![],
+            mapping: params("output: \"{{name}}.txt\"\nbar: \"{{name}}\""),
+        }],
+    }, &mut io);
+    assert_eq!(3, io.written.len());
+    io.assert_written("s2.txt", "foo s2 yay");
+    io.assert_written("solo.txt", "foo solo yay");
+}
+
+#[test]
+fn load_empty_yaml() {
+    assert_eq!(Err(YamlFileError::Empty), load_data("", DataFormat::Yaml));
+}
+
+fn incremental_pages() -> BuildAction {
+    BuildAction::BuildMultiplePages{
+        default_params: params("input: base01.txt"),
+        on: vec![BuildMultiplePages{
+            files: vec![],
+            params: vec![params("input: include01.txt\noutput: a.txt"), params("bar: b\noutput: b.txt")],
+            mapping: params("{}"),
+        }],
+    }
+}
+
+#[test]
+fn incremental_build_skips_unchanged_pages() {
+    let mut io = setup_io();
+    let mut ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    assert_eq!(Ok(()), incremental_pages().run_in(&ctx, &setup_pipes(), &mut io));
+    assert_eq!(2, io.written.len());
+    ctx.cache.take().unwrap().into_inner().unwrap().save("cache.yaml", &mut io).unwrap();
+
+    io.set_file("cache.yaml", &io.written["cache.yaml"].to_owned());
+    io.written.clear();
+    let next = incremental_pages();
+    ctx.cache = Some(Mutex::new(BuildCache::load("cache.yaml", &mut io).unwrap()));
+    assert_eq!(Ok(()), next.run_in(&ctx, &setup_pipes(), &mut io));
+    assert_eq!(0, io.written.len());
+    let cache = ctx.cache.take().unwrap().into_inner().unwrap();
+    assert_eq!(vec!["a.txt".to_string(), "b.txt".to_string()], cache.skipped);
+    assert_eq!("built 0, skipped 2 unchanged: a.txt, b.txt", cache.summary());
+}
+
+#[test]
+fn incremental_build_rebuilds_when_inputs_change() {
+    let mut io = setup_io();
+    let mut ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    assert_eq!(Ok(()), incremental_pages().run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("a.txt", "inc apple");
+    io.written.clear();
+    io.set_file("aaa.txt", "apricot");
+    assert_eq!(Ok(()), incremental_pages().run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("a.txt", "inc apricot");
+    assert_eq!(1, io.written.len());
+    io.set_file("base01.txt", "changed {{bar}}");
+    assert_eq!(Ok(()), incremental_pages().run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("b.txt", "changed b");
+    let cache = ctx.cache.take().unwrap().into_inner().unwrap();
+    assert_eq!(vec!["a.txt", "b.txt", "a.txt", "b.txt"], cache.built);
+    assert_eq!(vec!["b.txt", "a.txt"], cache.skipped);
+}
+
+#[test]
+fn incremental_build_rebuilds_when_params_change() {
+    let mut io = setup_io();
+    let ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    let page = |bar: &str| BuildAction::BuildPage{output: "out.txt".to_string(), input: "base01.txt".to_string(), params: params(bar)};
+    assert_eq!(Ok(()), page("bar: one").run_in(&ctx, &setup_pipes(), &mut io));
+    assert_eq!(Ok(()), page("bar: one").run_in(&ctx, &setup_pipes(), &mut io));
+    assert_eq!(Ok(()), page("bar: two").run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("out.txt", "foo two yay");
+    assert_eq!(vec!["out.txt".to_string()], ctx.cache.unwrap().into_inner().unwrap().skipped);
+}
+
+#[test]
+fn incremental_build_rebuilds_missing_outputs() {
+    let mut io = MemoryFs::from_files([("page.txt", "hello {{bar}}")]);
+    let ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    let page = BuildAction::BuildPage{output: "out/page.txt".to_string(), input: "page.txt".to_string(), params: params("bar: you")};
+    assert_eq!(Ok(()), page.run_in(&ctx, &setup_pipes(), &mut io));
+    io.remove("out/page.txt").unwrap();
+    assert_eq!(Ok(()), page.run_in(&ctx, &setup_pipes(), &mut io));
+    assert_eq!(Ok("hello you"), io.read("out/page.txt"));
+    assert_eq!(Ok(()), page.run_in(&ctx, &setup_pipes(), &mut io));
+    let cache = ctx.cache.unwrap().into_inner().unwrap();
+    assert_eq!(vec!["out/page.txt", "out/page.txt"], cache.built);
+    assert_eq!(vec!["out/page.txt"], cache.skipped);
+}
+
+#[test]
+fn incremental_runs_check_outputs_without_loading_them() {
+    let dir = temp_dir("build-exists", &[("page.txt", "hello {{bar}}")]);
+    let root = dir.to_string_lossy().into_owned();
+    let actions = vec![BuildAction::BuildPage{output: format!("{}/out.txt", root), input: format!("{}/page.txt", root), params: params("bar: you")}];
+    let ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    let mut io = FileCache::new();
+    for _ in 0..3 {
+        assert_eq!(Ok(()), run_actions(&actions, &ctx, &setup_pipes(), &mut io));
+    }
+    assert_eq!("hello {{bar}}".len(), io.cached_bytes());
+    let cache = ctx.cache.unwrap().into_inner().unwrap();
+    assert!(cache.built.is_empty());
+    assert_eq!(vec![format!("{}/out.txt", root)], cache.skipped);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn incremental_build_notices_overrides_bytes_and_listings() {
+    let mut io = MemoryFs::from_files([
+        ("theme/head.txt", b"theme head".to_vec()),
+        ("page.txt", b"{% file head.txt %} {{logo | file_size}}".to_vec()),
+        ("logo.png", vec![0xff, 0x00]),
+    ]);
+    let mut pipes = setup_pipes();
+    add_file_pipes(&mut pipes);
+    let mut ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    ctx.render.sources.search = vec!["site".to_owned(), "theme".to_owned()];
+    let page = BuildAction::BuildPage{output: "out.txt".to_string(), input: "page.txt".to_string(), params: params("logo: logo.png")};
+    assert_eq!(Ok(()), page.run_in(&ctx, &pipes, &mut io));
+    assert_eq!(Ok("theme head 2"), io.read("out.txt"));
+    io.set_file("site/head.txt", "site head");
+    assert_eq!(Ok(()), page.run_in(&ctx, &pipes, &mut io));
+    assert_eq!(Ok("site head 2"), io.read("out.txt"));
+    io.set_file("logo.png", vec![0xffu8, 0x00, 0x01]);
+    assert_eq!(Ok(()), page.run_in(&ctx, &pipes, &mut io));
+    assert_eq!(Ok("site head 3"), io.read("out.txt"));
+
+    let mut cache = BuildCache::new();
+    cache.record("list.txt", 1, &["theme".to_owned()], &mut io);
+    io.set_file("list.txt", "");
+    assert!(cache.is_fresh("list.txt", 1, &mut io));
+    io.set_file("theme/foot.txt", "foot");
+    assert!(!cache.is_fresh("list.txt", 1, &mut io));
+}
+
+fn failing_pages() -> BuildAction {
+    BuildAction::BuildMultiplePages{
+        default_params: params("input: base01.txt"),
+        on: vec![
+            BuildMultiplePages{
+                files: vec!["multiple01.yaml".to_string(), "entry1.yaml".to_string(), "nope.yaml".to_string()],
+                params: vec![],
+                mapping: params("output: \"{{name}}.txt\""),
+            },
+            BuildMultiplePages{
+                files: vec![],
+                params: vec![params("bar: fine\noutput: fine.txt"), params("output: broken.txt")],
+                mapping: params("{}"),
+            },
+        ],
+    }
+}
+
+#[test]
+fn build_multiple_pages_keeps_going() {
+    let mut io = setup_io();
+    let result = failing_pages().run(&setup_pipes(), &mut io);
+    assert_eq!(1, io.written.len());
+    io.assert_written("fine.txt", "foo fine yay");
+    let errors = match result {
+        Err(BuildError::Many(errors)) => errors,
+        other => panic!("expected many errors, got {:?}", other),
+    };
+    let non_map = |index| EntryError{
+        source: ParamsSource::File("entry1.yaml".to_string()),
+        index: Some(index),
+        error: BuildError::BMFContainsNonMap("entry1.yaml".to_string()),
+    };
+    assert_eq!(vec![
+        non_map(0),
+        non_map(1),
+        EntryError{
+            source: ParamsSource::File("nope.yaml".to_string()),
+            index: None,
+            error: BuildError::YamlFileError(YamlFileError::File(FileError::FileNotFound("nope.yaml".to_string()))),
+        },
+        EntryError{
+            source: ParamsSource::File("multiple01.yaml".to_string()),
+            index: Some(0),
+            error: BuildError::TemplateError(TemplateError::KeyNotPresent("bar".to_string())),
+        },
+        EntryError{
+            source: ParamsSource::File("multiple01.yaml".to_string()),
+            index: Some(1),
+            error: BuildError::TemplateError(TemplateError::KeyNotPresent("bar".to_string())),
+        },
+        EntryError{
+            source: ParamsSource::None,
+            index: Some(1),
+            error: BuildError::TemplateError(TemplateError::KeyNotPresent("bar".to_string())),
+        },
+    ], errors);
+}
+
+#[test]
+fn build_multiple_pages_fail_fast() {
+    let mut io = setup_io();
+    let ctx = BuildContext{fail_fast: true, ..BuildContext::default()};
+    assert_eq!(
+        Err(BuildError::BMFContainsNonMap("entry1.yaml".to_string())),
+        failing_pages().run_in(&ctx, &setup_pipes(), &mut io)
+    );
+    assert_eq!(0, io.written.len());
+}
+
+#[test]
+fn run_actions_collects_across_actions() {
+    let mut io = setup_io();
+    let actions = vec![
+        BuildAction::BuildPage{output: "a.txt".to_string(), input: "missing.txt".to_string(), params: params("{}")},
+        failing_pages(),
+        BuildAction::BuildPage{output: "b.txt".to_string(), input: "base01.txt".to_string(), params: params("bar: b")},
+    ];
+    let errors = match run_actions(&actions, &BuildContext::default(), &setup_pipes(), &mut io) {
+        Err(BuildError::Many(errors)) => errors,
+        other => panic!("expected many errors, got {:?}", other),
+    };
+    io.assert_written("b.txt", "foo b yay");
+    assert_eq!(7, errors.len());
+    assert_eq!(ParamsSource::Action(0), errors[0].source);
+    assert!(error_summary(&errors).starts_with(
+        "7 errors\n  action 0:\n    FileError(FileNotFound(\"missing.txt\"))\n  entry1.yaml:\n    entry 0: BMFContainsNonMap(\"entry1.yaml\")\n"
+    ));
+    let ctx = BuildContext{fail_fast: true, ..BuildContext::default()};
+    assert_eq!(
+        Err(BuildError::FileError(FileError::FileNotFound("missing.txt".to_string()))),
+        run_actions(&actions, &ctx, &setup_pipes(), &mut io)
+    );
+}
+
+#[test]
+fn layouts_resolve_through_search_paths() {
+    let mut io = setup_io();
+    io.set_file("theme/page.txt", "---\nlayout: base.txt\n---\nbody");
+    io.set_file("theme/base.txt", "theme [{{content}}]");
+    io.set_file("site/base.txt", "site [{{content}}]");
+    let mut ctx = BuildContext::default();
+    ctx.render.sources = SourcePaths{search: vec!["site".to_string(), "theme".to_string()], ..SourcePaths::default()};
+    let action = BuildAction::BuildPage{output: "out.txt".to_string(), input: "theme/page.txt".to_string(), params: params("{}")};
+    assert_eq!(Ok(()), action.run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("out.txt", "site [body]");
+}
+
+#[test]
+fn inputs_resolve_through_source_paths() {
+    let mut io = setup_io();
+    io.set_file("proj/theme/page.txt", "theme page");
+    io.set_file("proj/theme/about.txt", "theme about");
+    io.set_file("proj/site/page.txt", "site page");
+    let mut ctx = BuildContext::default();
+    ctx.render.sources = SourcePaths{root: "proj".to_string(), search: vec!["site".to_string(), "theme".to_string()], ..SourcePaths::default()};
+    for (input, expected) in [("page.txt", "site page"), ("about.txt", "theme about"), ("aaa.txt", "apple")] {
+        let action = BuildAction::BuildPage{output: "out.txt".to_string(), input: input.to_string(), params: params("{}")};
+        assert_eq!(Ok(()), action.run_in(&ctx, &setup_pipes(), &mut io));
+        io.assert_written("out.txt", expected);
+    }
+}
+
+#[test]
+fn templates_are_parsed_once_per_build() {
+    let mut io = setup_io();
+    let ctx = BuildContext::default();
+    let action = BuildAction::BuildMultiplePages{
+        default_params: params("input: front02.txt"),
+        on: vec![BuildMultiplePages{
+            files: vec![],
+            params: (0..50).map(|ii| params(&format!("num: {}", ii))).collect(),
+            mapping: params("output: \"out{{num}}.txt\""),
+        }],
+    };
+    assert_eq!(Ok(()), action.run_in(&ctx, &setup_pipes(), &mut io));
+    io.assert_written("out49.txt", "<[inner: body inner] fromlayout>");
+    //the page, its two layouts and the output mapping
+    assert_eq!(4, ctx.render.templates.parses());
+}
+
+#[test]
+fn template_store_replaces_changed_files() {
+    let store = TemplateStore::new();
+    let first = store.parse("page.txt", "a {{bar}}").unwrap();
+    assert!(std::sync::Arc::ptr_eq(&first, &store.parse("page.txt", "a {{bar}}").unwrap()));
+    store.parse("page.txt", "b {{bar}}").unwrap();
+    store.parse("other.txt", "a {{bar}}").unwrap();
+    assert_eq!(2, store.len());
+    assert_eq!(3, store.parses());
+    assert!(store.parse("bad.txt", "{{").is_err());
+    assert_eq!(2, store.len());
+}
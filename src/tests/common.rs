@@ -1,18 +1,29 @@
 use crate::template::{render, TemplateError};
 use crate::pipes::{PipeMap, PipeDefinition, new_pipe_map};
 use crate::parsers::{parse_template_string};
-use crate::io::{ReadsFiles, FileError};
-use crate::yaml::{load_yaml, YamlValue, YamlFileError};
+use crate::io::{ReadsFiles, FileError, CopyMode};
+use crate::memory::MemoryFs;
+use crate::yaml::{load_data, YamlValue, YamlFileError, DataFormat};
 use yaml_rust2::{yaml::{Hash, Yaml}, YamlLoader};
 use std::collections::HashMap;
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
 
 pub struct TestFileCache {
     files: HashMap<String, String>,
     yamls: HashMap<String, YamlValue>,
     pub written: HashMap<String, String>,
+    //from, to
+    pub copied: RefCell<Vec<(String, String)>>,
+    pub copied_files: RefCell<Vec<(String, String, CopyMode)>>,
 }
 
 impl TestFileCache {
+    pub fn set_file(&mut self, filename: &str, contents: &str) {
+        self.files.insert(filename.to_owned(), contents.to_owned());
+    }
+
     pub fn assert_written(&self, filename: &str, contents: &str) {
         assert_eq!(
             self.written.get(filename)
@@ -30,19 +41,47 @@ impl ReadsFiles for TestFileCache {
         }
     }
 
-    fn read_yaml(&mut self, filename: &str) -> Result<&YamlValue, YamlFileError> {
+    fn read_data(&mut self, filename: &str, format: DataFormat) -> Result<&YamlValue, YamlFileError> {
         let contents = self.read(filename).map_err(|xx| YamlFileError::File(xx))?;
-        let loaded = load_yaml(contents).map_err(|xx| YamlFileError::Yaml(xx))?;
+        let loaded = load_data(contents, format)?;
         self.yamls.insert(filename.to_owned(), loaded);
         Ok(self.yamls.get(filename).unwrap())
     }
+    //written files can be read back, like on disk
     fn write(&mut self, filename: &str, contents: &str) -> Result<(), FileError> {
         self.written.insert(filename.to_owned(), contents.to_owned());
+        self.files.insert(filename.to_owned(), contents.to_owned());
         Ok(())
     }
     fn copy_files(&self, from: &str, to: &str) -> Result<(), FileError> {
+        self.copied.borrow_mut().push((from.to_owned(), to.to_owned()));
+        Ok(())
+    }
+    fn copy_file(&self, from: &str, to: &str, mode: CopyMode) -> Result<(), FileError> {
+        if !self.files.contains_key(from) {
+            return Err(FileError::FilesCantBeCopied(from.to_owned()));
+        }
+        self.copied_files.borrow_mut().push((from.to_owned(), to.to_owned(), mode));
         Ok(())
     }
+    fn list_files(&mut self, dir: &str) -> Result<Vec<String>, FileError> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut found: Vec<String> = self.files.keys()
+            .filter(|ii| ii.starts_with(&prefix))
+            .cloned()
+            .collect();
+        if found.is_empty() {
+            return Err(FileError::FileNotFound(dir.to_owned()));
+        }
+        found.sort();
+        Ok(found)
+    }
+    fn remove(&mut self, filename: &str) -> Result<(), FileError> {
+        match self.files.remove(filename) {
+            Some(_) => Ok(()),
+            None => Err(FileError::FileNotFound(filename.to_owned())),
+        }
+    }
 }
 
 pub fn setup_io() -> TestFileCache {
@@ -56,7 +95,33 @@ pub fn setup_io() -> TestFileCache {
     files.insert("entry1.yaml".to_string(), "[9, 8]".to_string());
     files.insert("entry2.yaml".to_string(), "[\"asd\", \"fgh\"]".to_string());
     files.insert("base01.txt".to_string(), "foo {{bar}} yay".to_string());
-    TestFileCache{files, yamls: HashMap::new(), written: HashMap::new()}
+    files.insert("front01.txt".to_string(), "---\nbar: front\ntitle: hello\n---\n{{title}} {{bar}}".to_string());
+    files.insert("front02.txt".to_string(), "---\ntitle: inner\nlayout: layout01.txt\n---\nbody {{title}}".to_string());
+    files.insert("layout01.txt".to_string(), "---\nbar: fromlayout\nlayout: layout02.txt\n---\n[{{title}}: {{content}}]".to_string());
+    files.insert("layout02.txt".to_string(), "<{{content}} {{bar}}>".to_string());
+    files.insert("layoutcycle.txt".to_string(), "---\nlayout: layoutcycle.txt\n---\nx".to_string());
+    files.insert("multiple01.yaml".to_string(), "[{name: one}, {name: two, title: entry}]".to_string());
+    files.insert("data/nav.yaml".to_string(), "[home, about]".to_string());
+    files.insert("data/config.json".to_string(), "{\"name\": \"epic\"}".to_string());
+    files.insert("data/authors/alice.yaml".to_string(), "name: Alice".to_string());
+    files.insert("data/notes.txt".to_string(), "not data".to_string());
+    files.insert("site01.txt".to_string(), "{{site.data.config.name}} {{site.data.nav[1]}} {{site.data.authors.alice.name}}".to_string());
+    files.insert("people.csv".to_string(), "name,age\nann,30\n\"bo, jr\",4".to_string());
+    files.insert("people.txt".to_string(), "name,age\ncy,9".to_string());
+    files.insert("config.toml".to_string(), "title = \"t\"\n[[items]]\nn = 1\n[[items]]\nn = 2.5".to_string());
+    files.insert("list.json".to_string(), "[{\"name\": \"j1\"}, {\"name\": \"j2\"}]".to_string());
+    files.insert("empty.yaml".to_string(), "".to_string());
+    files.insert("stream.yaml".to_string(), "---\nname: s1\nbar: first\n---\nname: s2\nbar: second\n".to_string());
+    files.insert("single.yaml".to_string(), "---\nname: solo\n".to_string());
+    files.insert("merged.yaml".to_string(), "- &defaults {name: one, colour: red}\n- <<: *defaults\n  name: two\n  colour: blue".to_string());
+    files.insert("include01.txt".to_string(), "inc {% file aaa.txt %}".to_string());
+    TestFileCache{
+        files,
+        yamls: HashMap::new(),
+        written: HashMap::new(),
+        copied: RefCell::new(vec![]),
+        copied_files: RefCell::new(vec![]),
+    }
 }
 
 pub fn setup_pipes() -> PipeMap {
@@ -68,4 +133,36 @@ pub fn setup_pipes() -> PipeMap {
     pipemap
 }
 
+//a yaml map from yaml source, for params and expected front matter
+pub fn params(strr: &str) -> Hash {
+    YamlLoader::load_from_str(strr).unwrap()[0].as_hash().expect("not a hash map?").clone()
+}
+
+//an empty `epicsitegen-<name>-<pid>` directory under the system temp dir, with these files written
+//into it, making whatever directories they need
+pub fn temp_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("epicsitegen-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, contents) in files {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
 
+//a small site in memory: pages with a layout and a data file to build them from, a feed, posts with a
+//draft among them, and static files
+pub fn memory_site() -> MemoryFs {
+    MemoryFs::from_files([
+        ("pages/page.html", "---\nlayout: layouts/base.html\n---\n<p>{{title}}</p>"),
+        ("layouts/base.html", "<main>{{content}}</main>"),
+        ("pages.yaml", "- {title: one, slug: one}\n- {title: two, slug: two}"),
+        ("page.html", "<body>{{title}}</body>"),
+        ("feed.xml", "<feed>{{title}}</feed>"),
+        ("posts.yaml", "- {title: one, slug: one}\n- {title: two, slug: two, draft: true}"),
+        ("static/css/app.css", "body {}"),
+        ("static/img/logo.png", "png"),
+    ])
+}
@@ -0,0 +1,216 @@
+use crate::assets::{Fingerprint, fingerprinted_name};
+use crate::build::{BuildAction, BuildContext, BuildError, run_actions};
+use crate::copy::{CopyOptions, expand_braces};
+use crate::io::{CopyMode, FileCache, ReadsFiles};
+use crate::memory::MemoryFs;
+use crate::incremental::BuildCache;
+use std::sync::Mutex;
+use crate::tests::common::{TestFileCache, setup_io, setup_pipes, temp_dir};
+use std::fs;
+
+fn with_static_dir() -> TestFileCache {
+    let mut io = setup_io();
+    io.set_file("static/logo.png", "png");
+    io.set_file("static/icons/menu.svg", "svg");
+    io.set_file("static/icons/.DS_Store", "junk");
+    io.set_file("static/art/cover.psd", "psd");
+    io.set_file("static/css/app.css", "css");
+    io
+}
+
+fn copy(io: &mut TestFileCache, from: &str, options: CopyOptions) -> Result<Vec<(String, String)>, BuildError> {
+    let action = BuildAction::CopyFiles{to: "out".to_owned(), from: from.to_owned(), options};
+    action.run(&setup_pipes(), io)?;
+    Ok(io.copied_files.borrow().iter().map(|(from, to, _)| (from.to_owned(), to.to_owned())).collect())
+}
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect()
+}
+
+#[test]
+fn braces_expand_to_every_alternative() {
+    assert_eq!(vec!["**/*.png", "**/*.svg"], expand_braces("**/*.{png,svg}"));
+    assert_eq!(vec!["a/x.c", "a/y.c", "b/x.c", "b/y.c"], expand_braces("{a,b}/{x,y}.c"));
+    assert_eq!(vec!["plain"], expand_braces("plain"));
+}
+
+#[test]
+fn copy_files_is_called_from_then_to() {
+    let mut io = setup_io();
+    let action = BuildAction::CopyFiles{to: "out/static".to_owned(), from: "static".to_owned(), options: CopyOptions::default()};
+    assert_eq!(Ok(()), action.run(&setup_pipes(), &mut io));
+    assert_eq!(vec![("static".to_owned(), "out/static".to_owned())], *io.copied.borrow());
+}
+
+#[test]
+fn copy_includes_and_excludes() {
+    let mut io = with_static_dir();
+    let options = CopyOptions{
+        include: vec!["**/*.{png,svg,psd}".to_owned()],
+        exclude: vec!["*.psd".to_owned(), ".DS_Store".to_owned()],
+        ..CopyOptions::default()
+    };
+    assert_eq!(
+        Ok(pairs(&[("static/icons/menu.svg", "out/icons/menu.svg"), ("static/logo.png", "out/logo.png")])),
+        copy(&mut io, "static", options)
+    );
+}
+
+#[test]
+fn copy_excludes_by_name_anywhere() {
+    let mut io = with_static_dir();
+    let options = CopyOptions{exclude: vec![".DS_Store".to_owned(), "art".to_owned()], ..CopyOptions::default()};
+    let copied = copy(&mut io, "static/", options).unwrap();
+    assert_eq!(3, copied.len());
+    assert!(copied.iter().all(|(from, _)| !from.ends_with(".DS_Store") && !from.ends_with(".psd")));
+}
+
+#[test]
+fn copy_renames_and_flattens() {
+    let mut io = with_static_dir();
+    let options = CopyOptions{
+        include: vec!["icons/*".to_owned(), "css/*".to_owned()],
+        exclude: vec![".*".to_owned()],
+        rename: Some("flat/{{stem}}.min.{{ext}}".to_owned()),
+        mode: CopyMode::Symlink,
+        fingerprint: None,
+    };
+    assert_eq!(
+        Ok(pairs(&[("static/css/app.css", "out/flat/app.min.css"), ("static/icons/menu.svg", "out/flat/menu.min.svg")])),
+        copy(&mut io, "static", options)
+    );
+    assert!(io.copied_files.borrow().iter().all(|(_, _, mode)| *mode == CopyMode::Symlink));
+}
+
+#[test]
+fn copy_single_file_with_options() {
+    let mut io = with_static_dir();
+    let options = CopyOptions{mode: CopyMode::Hardlink, ..CopyOptions::default()};
+    assert_eq!(Ok(pairs(&[("static/logo.png", "out")])), copy(&mut io, "static/logo.png", options));
+}
+
+#[test]
+fn copy_reports_bad_globs() {
+    let mut io = with_static_dir();
+    let options = CopyOptions{include: vec!["[".to_owned()], ..CopyOptions::default()};
+    assert!(matches!(copy(&mut io, "static", options), Err(BuildError::BadGlob(..))));
+}
+
+#[test]
+fn file_cache_links_and_copies() {
+    let dir = temp_dir("copy", &[("static/sub/a.txt", "aaa"), ("static/b.md", "bbb")]);
+    let root = dir.to_string_lossy().into_owned();
+    let mut io = FileCache::new();
+    for (mode, out) in [(CopyMode::Copy, "copied"), (CopyMode::Symlink, "symlinked"), (CopyMode::Hardlink, "hardlinked")] {
+        let action = BuildAction::CopyFiles{
+            to: format!("{}/{}", root, out),
+            from: format!("{}/static", root),
+            options: CopyOptions{include: vec!["*.txt".to_owned()], mode, ..CopyOptions::default()},
+        };
+        assert_eq!(Ok(()), action.run_in(&BuildContext::default(), &setup_pipes(), &mut io));
+        assert_eq!("aaa", fs::read_to_string(dir.join(out).join("sub/a.txt")).unwrap());
+        assert!(!dir.join(out).join("b.md").exists());
+    }
+    assert!(fs::symlink_metadata(dir.join("symlinked/sub/a.txt")).unwrap().file_type().is_symlink());
+    assert!(!fs::symlink_metadata(dir.join("hardlinked/sub/a.txt")).unwrap().file_type().is_symlink());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn switching_modes_leaves_the_source_alone() {
+    let dir = temp_dir("copy-modes", &[("static/app.css", "body {}")]);
+    let root = dir.to_string_lossy().into_owned();
+    let mut io = FileCache::new();
+    for mode in [CopyMode::Hardlink, CopyMode::Copy, CopyMode::Symlink, CopyMode::Copy, CopyMode::Copy] {
+        let action = BuildAction::CopyFiles{
+            to: format!("{}/out", root),
+            from: format!("{}/static", root),
+            options: CopyOptions{mode, ..CopyOptions::default()},
+        };
+        assert_eq!(Ok(()), action.run_in(&BuildContext::default(), &setup_pipes(), &mut io));
+        assert_eq!("body {}", fs::read_to_string(dir.join("static/app.css")).unwrap());
+        assert_eq!("body {}", fs::read_to_string(dir.join("out/app.css")).unwrap());
+    }
+    assert!(!fs::symlink_metadata(dir.join("out/app.css")).unwrap().file_type().is_symlink());
+    let from = dir.join("static/app.css").to_string_lossy().into_owned();
+    assert_eq!(Ok(()), io.copy_file(&from, &from, CopyMode::Copy));
+    assert_eq!("body {}", fs::read_to_string(dir.join("static/app.css")).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn fingerprinted_names_keep_the_extension() {
+    let hash = &fingerprinted_name("x", b"css")[2..];
+    assert_eq!(8, hash.len());
+    assert_eq!(format!("css/app.{}.css", hash), fingerprinted_name("css/app.css", b"css"));
+    assert_eq!(format!("v1.2/.htaccess.{}", hash), fingerprinted_name("v1.2/.htaccess", b"css"));
+    assert_ne!(fingerprinted_name("app.css", b"css"), fingerprinted_name("app.css", b"css2"));
+}
+
+#[test]
+fn fingerprinted_copies_feed_the_asset_pipe() {
+    let mut io = MemoryFs::from_files([
+        ("static/css/app.css", "body {}"),
+        ("static/logo.png", "png"),
+        ("page.html", "<link href=\"{{ \"css/app.css\" | asset }}\">"),
+    ]);
+    let ctx = BuildContext::default();
+    let mut pipes = setup_pipes();
+    pipes.insert("asset".to_owned(), ctx.render.assets.pipe());
+    let fingerprint = Fingerprint{url_prefix: "/static/".to_owned(), manifest: Some("out/assets.json".to_owned())};
+    let actions = vec![
+        BuildAction::CopyFiles{
+            to: "out/static".to_owned(),
+            from: "static".to_owned(),
+            options: CopyOptions{fingerprint: Some(fingerprint), ..CopyOptions::default()},
+        },
+        BuildAction::BuildPage{output: "out/index.html".to_owned(), input: "page.html".to_owned(), params: Default::default()},
+    ];
+    run_actions(&actions, &ctx, &pipes, &mut io).unwrap();
+    let (css, png) = (fingerprinted_name("css/app.css", b"body {}"), fingerprinted_name("logo.png", b"png"));
+    assert_eq!(Ok("body {}"), io.read(&format!("out/static/{}", css)));
+    assert_eq!(Ok(format!("<link href=\"/static/{}\">", css).as_str()), io.read("out/index.html"));
+    assert_eq!(
+        Ok(format!("{{\"css/app.css\":\"/static/{}\",\"logo.png\":\"/static/{}\"}}", css, png).as_str()),
+        io.read("out/assets.json")
+    );
+    assert!(io.read("out/static/css/app.css").is_err());
+}
+
+#[test]
+fn cached_pages_follow_changed_fingerprints() {
+    let mut io = MemoryFs::from_files([
+        ("static/app.css", "body {}"),
+        ("page.html", "{{ \"app.css\" | asset }}"),
+    ]);
+    let ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    let mut pipes = setup_pipes();
+    pipes.insert("asset".to_owned(), ctx.render.assets.pipe());
+    let actions = vec![
+        BuildAction::CopyFiles{
+            to: "out".to_owned(),
+            from: "static".to_owned(),
+            options: CopyOptions{fingerprint: Some(Fingerprint::default()), ..CopyOptions::default()},
+        },
+        BuildAction::BuildPage{output: "out/index.html".to_owned(), input: "page.html".to_owned(), params: Default::default()},
+    ];
+    run_actions(&actions, &ctx, &pipes, &mut io).unwrap();
+    assert_eq!(Ok(fingerprinted_name("app.css", b"body {}").as_str()), io.read("out/index.html"));
+    io.set_file("static/app.css", "body {color: red}");
+    ctx.render.assets.insert("gone.css", "gone.1234.css", "static/gone.css");
+    run_actions(&actions, &ctx, &pipes, &mut io).unwrap();
+    assert_eq!(Ok(fingerprinted_name("app.css", b"body {color: red}").as_str()), io.read("out/index.html"));
+    assert_eq!(None, ctx.render.assets.get("gone.css"));
+}
+
+#[test]
+fn unknown_assets_are_errors() {
+    let mut io = MemoryFs::from_files([("page.html", "{{ \"app.css\" | asset }}")]);
+    let ctx = BuildContext::default();
+    let mut pipes = setup_pipes();
+    pipes.insert("asset".to_owned(), ctx.render.assets.pipe());
+    let action = BuildAction::BuildPage{output: "out.html".to_owned(), input: "page.html".to_owned(), params: Default::default()};
+    assert!(action.run_in(&ctx, &pipes, &mut io).is_err());
+    assert!(ctx.render.assets.is_empty());
+}
@@ -0,0 +1,237 @@
+use crate::io::{CacheStats, FileCache, FileError, ReadsFiles, Sandbox, WriteStats};
+use crate::yaml::YamlValue;
+use crate::template::{RenderOptions, SourcePaths, TemplateError, render_with};
+use crate::memory::MemoryFs;
+use crate::pipes::add_file_pipes;
+use crate::tests::common::{params, setup_pipes, temp_dir};
+use crate::utils::base64;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn write_creates_parent_dirs() {
+    let dir = temp_dir("io-parents", &[]);
+    let output = dir.join("deep/er/page.html");
+    let mut io = FileCache::new();
+    assert_eq!(Ok(()), io.write(&output.to_string_lossy(), "hello"));
+    assert_eq!("hello", fs::read_to_string(&output).unwrap());
+    //no temporary files are left behind
+    assert_eq!(1, fs::read_dir(dir.join("deep/er")).unwrap().count());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn write_skips_identical_contents() {
+    let dir = temp_dir("io-identical", &[]);
+    let output = dir.join("page.html").to_string_lossy().into_owned();
+    let mut io = FileCache::new();
+    io.write(&output, "same").unwrap();
+    let modified = fs::metadata(&output).unwrap().modified().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    io.write(&output, "same").unwrap();
+    assert_eq!(modified, fs::metadata(&output).unwrap().modified().unwrap());
+    io.write(&output, "different").unwrap();
+    assert_eq!(WriteStats{written: 2, unchanged: 1}, io.stats);
+    assert_eq!("wrote 2, 1 unchanged", io.stats.to_string());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn write_updates_cached_reads() {
+    let dir = temp_dir("io-cached", &[]);
+    let output = dir.join("page.html").to_string_lossy().into_owned();
+    let mut io = FileCache::new();
+    io.write(&output, "before").unwrap();
+    assert_eq!(Ok("before"), io.read(&output));
+    io.write(&output, "after").unwrap();
+    assert_eq!(Ok("after"), io.read(&output));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bytes_round_trip() {
+    let dir = temp_dir("io-bytes", &[]);
+    let output = dir.join("img/dot.png").to_string_lossy().into_owned();
+    let mut io = FileCache::new();
+    let png: &[u8] = &[0x89, b'P', b'N', b'G', 0xff, 0x00];
+    io.write_bytes(&output, png).unwrap();
+    io.write_bytes(&output, png).unwrap();
+    assert_eq!(WriteStats{written: 1, unchanged: 1}, io.stats);
+    assert_eq!(Ok(png), io.read_bytes(&output));
+    assert_eq!(Err(FileError::FileCantBeRead(output.clone())), io.read(&output));
+    //text reads and byte reads share the one cached copy
+    let text = dir.join("a.txt").to_string_lossy().into_owned();
+    fs::write(&text, "hello").unwrap();
+    assert_eq!(Ok("hello"), io.read(&text));
+    assert_eq!(Ok(&b"hello"[..]), io.read_bytes(&text));
+    assert_eq!(CacheStats{hits: 2, misses: 2, reloads: 0, evictions: 0}, io.reads);
+    assert_eq!(11, io.cached_bytes());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn base64_pads() {
+    assert_eq!("", base64(b""));
+    assert_eq!("Zg==", base64(b"f"));
+    assert_eq!("Zm8=", base64(b"fo"));
+    assert_eq!("Zm9v", base64(b"foo"));
+    assert_eq!("/+8A", base64(&[0xff, 0xef, 0x00]));
+}
+
+#[test]
+fn file_pipes_read_bytes() {
+    let mut io = MemoryFs::from_files([("img/dot.png", vec![0xffu8, 0xef, 0x00]), ("app.css", b"a{}".to_vec())]);
+    let mut pipes = setup_pipes();
+    add_file_pipes(&mut pipes);
+    let params = params("{logo: img/dot.png, style: app.css, missing: nope.png}");
+    let render = |template: &str, io: &mut MemoryFs| render_with(template, &params, &pipes, &RenderOptions::default(), io);
+    assert_eq!(Ok("data:image/png;base64,/+8A".to_owned()), render("{{logo | data_uri}}", &mut io));
+    assert_eq!(Ok("data:text/css;charset=utf-8;base64,YXt9".to_owned()), render("{{style | data_uri}}", &mut io));
+    assert_eq!(Ok("/+8A 3".to_owned()), render("{{logo | base64}} {{logo | file_size}}", &mut io));
+    assert_eq!(
+        Err(TemplateError::PipeExecutionError("FileNotFound(\"nope.png\")".to_owned())),
+        render("{{missing | file_size}}", &mut io)
+    );
+}
+
+fn sandbox_dirs(name: &str) -> (PathBuf, String, String) {
+    let dir = temp_dir(&format!("io-{}", name), &[]);
+    fs::create_dir_all(dir.join("root")).unwrap();
+    fs::create_dir_all(dir.join("outside")).unwrap();
+    fs::write(dir.join("root/page.txt"), "page").unwrap();
+    fs::write(dir.join("outside/secret.txt"), "secret").unwrap();
+    std::os::unix::fs::symlink(dir.join("outside/secret.txt"), dir.join("root/link.txt")).unwrap();
+    let root = dir.join("root").to_string_lossy().into_owned();
+    let outside = dir.join("outside").to_string_lossy().into_owned();
+    (dir, root, outside)
+}
+
+#[test]
+fn sandbox_confines_reads() {
+    let (dir, root, outside) = sandbox_dirs("sandbox-reads");
+    let mut io = FileCache::sandboxed(Sandbox::new(&[&root]).unwrap());
+    assert_eq!(Ok("page"), io.read(&format!("{}/page.txt", root)));
+    assert_eq!(Ok("page"), io.read(&format!("{}/../root/page.txt", root)));
+    for escape in [format!("{}/../outside/secret.txt", root), format!("{}/secret.txt", outside), format!("{}/link.txt", root)] {
+        assert_eq!(Err(FileError::OutsideSandbox(escape.to_owned())), io.read(&escape));
+    }
+    assert_eq!(Err(FileError::FileNotFound(format!("{}/missing.txt", root))), io.read(&format!("{}/missing.txt", root)));
+    assert_eq!(
+        Err(FileError::OutsideSandbox(format!("{}/nothere/../../outside/secret.txt", root))),
+        io.read(&format!("{}/nothere/../../outside/secret.txt", root))
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sandbox_confines_writes_and_copies() {
+    let (dir, root, outside) = sandbox_dirs("sandbox-writes");
+    let mut io = FileCache::sandboxed(Sandbox::new(&[&root]).unwrap().allow(&outside).unwrap());
+    assert_eq!(Ok("secret"), io.read(&format!("{}/secret.txt", outside)));
+    assert_eq!(Ok(()), io.write(&format!("{}/new/dir/out.txt", root), "out"));
+    let escape = format!("{}/../outside/out.txt", root);
+    assert_eq!(Err(FileError::OutsideSandbox(escape.to_owned())), io.write(&escape, "out"));
+    assert_eq!(Err(FileError::OutsideSandbox(outside.to_owned())), io.copy_files(&root, &outside));
+    assert_eq!(Ok(()), io.copy_files(&format!("{}/secret.txt", outside), &format!("{}/copied.txt", root)));
+    assert_eq!("secret", fs::read_to_string(dir.join("root/copied.txt")).unwrap());
+    assert!(!dir.join("outside/out.txt").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sandbox_rejects_dirs_with_escaping_links() {
+    let (dir, root, _) = sandbox_dirs("sandbox-links");
+    let io = FileCache::sandboxed(Sandbox::new(&[&root]).unwrap());
+    let link = format!("{}/link.txt", root);
+    assert_eq!(Err(FileError::OutsideSandbox(link)), io.copy_files(&root, &format!("{}/copy", root)));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sandbox_applies_to_templates() {
+    let (dir, root, _) = sandbox_dirs("sandbox-templates");
+    let mut io = FileCache::sandboxed(Sandbox::new(&[&root]).unwrap());
+    let params = params("name: ../outside/secret.txt");
+    let options = RenderOptions{sources: SourcePaths{root: root.to_owned(), ..SourcePaths::default()}, ..RenderOptions::default()};
+    assert_eq!(
+        Err(TemplateError::FileError(FileError::OutsideSandbox(format!("{}/../outside/secret.txt", root)))),
+        render_with("{% file @ name %}", &params, &setup_pipes(), &options, &mut io)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cache_counts_hits_and_misses() {
+    let dir = temp_dir("io-stats", &[]);
+    let file = dir.join("a.txt").to_string_lossy().into_owned();
+    fs::write(&file, "aaa").unwrap();
+    let mut io = FileCache::new();
+    io.read(&file).unwrap();
+    io.read(&file).unwrap();
+    io.invalidate(&file);
+    io.read(&file).unwrap();
+    assert_eq!(CacheStats{hits: 1, misses: 2, reloads: 0, evictions: 0}, io.reads);
+    assert_eq!("1 hits, 2 misses, 0 reloaded, 0 evicted", io.reads.to_string());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cache_rereads_changed_files_and_their_data() {
+    let dir = temp_dir("io-changes", &[]);
+    let file = dir.join("a.yaml").to_string_lossy().into_owned();
+    fs::write(&file, "[1]").unwrap();
+    let mut stale = FileCache::new();
+    let mut checking = FileCache::new().checking_changes();
+    assert_eq!(Ok(&YamlValue::Array(vec![YamlValue::Integer(1)])), checking.read_yaml(&file));
+    stale.read(&file).unwrap();
+    fs::write(&file, "[1, 2]").unwrap();
+    assert_eq!(Ok("[1]"), stale.read(&file));
+    assert_eq!(Ok(&YamlValue::Array(vec![YamlValue::Integer(1), YamlValue::Integer(2)])), checking.read_yaml(&file));
+    assert_eq!(1, checking.reads.reloads);
+    fs::remove_file(&file).unwrap();
+    assert_eq!(Err(FileError::FileNotFound(file.to_owned())), checking.read(&file));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cache_evicts_least_recently_read() {
+    let dir = temp_dir("io-budget", &[]);
+    let files: Vec<String> = ["a", "b", "c"].iter().map(|name| {
+        let file = dir.join(format!("{}.txt", name)).to_string_lossy().into_owned();
+        fs::write(&file, "0123456789").unwrap();
+        file
+    }).collect();
+    let mut io = FileCache::new().with_budget(25);
+    io.read(&files[0]).unwrap();
+    io.read(&files[1]).unwrap();
+    io.read(&files[0]).unwrap();
+    io.read(&files[2]).unwrap();
+    assert_eq!(20, io.cached_bytes());
+    assert_eq!(1, io.reads.evictions);
+    io.read(&files[0]).unwrap();
+    io.read(&files[1]).unwrap();
+    assert_eq!(CacheStats{hits: 2, misses: 4, reloads: 0, evictions: 2}, io.reads);
+    io.invalidate_dir(&dir.to_string_lossy());
+    assert_eq!(0, io.cached_bytes());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cache_invalidates_however_the_file_is_named() {
+    let dir = temp_dir("io-names", &[]);
+    fs::write(dir.join("a.txt"), "one").unwrap();
+    //the same file as `./../../tmp/...`, relative to the working directory
+    let depth = std::env::current_dir().unwrap().components().count() - 1;
+    let relative = format!("{}{}/a.txt", "../".repeat(depth), dir.to_string_lossy().trim_start_matches('/'));
+    let mut io = FileCache::new();
+    assert_eq!(Ok("one"), io.read(&format!("./{}", relative)));
+    fs::write(dir.join("a.txt"), "two").unwrap();
+    io.invalidate(&relative);
+    assert_eq!(Ok("two"), io.read(&format!("./{}", relative)));
+    fs::write(dir.join("a.txt"), "three").unwrap();
+    io.invalidate_dir(&relative[..relative.len() - "/a.txt".len()]);
+    assert_eq!(Ok("three"), io.read(&format!("./{}", relative)));
+    fs::remove_dir_all(&dir).unwrap();
+}
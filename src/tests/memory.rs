@@ -0,0 +1,82 @@
+use crate::build::{BuildAction, BuildContext, BuildMultiplePages, run_actions};
+use crate::copy::CopyOptions;
+use crate::io::{FileError, ReadsFiles};
+use crate::memory::MemoryFs;
+use crate::utils::clean_path;
+use crate::prune::{PruneOptions, build_and_prune};
+use crate::tests::common::{memory_site, params, setup_pipes, temp_dir};
+use crate::yaml::YamlValue;
+use std::fs;
+
+#[test]
+fn paths_are_cleaned() {
+    assert_eq!("a/b", clean_path("./a//x/../b/"));
+    assert_eq!("/a", clean_path("/../a"));
+    assert_eq!("../../b", clean_path("a/../..\\../b"));
+    assert_eq!("", clean_path("./"));
+    let mut memory = MemoryFs::from_files([("a/b.txt", "b")]);
+    assert_eq!(Ok("b"), memory.read("./a/c/../b.txt"));
+}
+
+#[test]
+fn binary_files_copy_but_dont_read() {
+    let mut memory = MemoryFs::from_files([("img.bin", vec![0xff, 0xfe])]);
+    assert_eq!(Err(FileError::FileCantBeRead("img.bin".to_owned())), memory.read("img.bin"));
+    assert_eq!(Ok(()), memory.copy_files("img.bin", "out/img.bin"));
+    assert_eq!(vec!["img.bin", "out/img.bin"], memory.filenames());
+}
+
+#[test]
+fn copy_files_copies_dirs() {
+    let mut memory = memory_site();
+    assert_eq!(Ok(()), memory.copy_files("static", "out/static"));
+    assert_eq!(Ok(vec!["out/static/css/app.css".to_owned(), "out/static/img/logo.png".to_owned()]), memory.list_files("out"));
+    assert_eq!(Err(FileError::FilesCantBeCopied("missing".to_owned())), memory.copy_files("missing", "out"));
+    assert_eq!(
+        Err(FileError::CantCopyDirIntoFile("static".to_owned(), "pages.yaml".to_owned())),
+        memory.copy_files("static", "pages.yaml")
+    );
+}
+
+#[test]
+fn copies_replace_parsed_data() {
+    let mut memory = MemoryFs::from_files([("a.yaml", "[1]"), ("b.yaml", "[2]")]);
+    assert_eq!(Ok(&YamlValue::Array(vec![YamlValue::Integer(1)])), memory.read_yaml("a.yaml"));
+    memory.copy_files("b.yaml", "a.yaml").unwrap();
+    assert_eq!(Ok(&YamlValue::Array(vec![YamlValue::Integer(2)])), memory.read_yaml("a.yaml"));
+}
+
+#[test]
+fn whole_site_builds_in_memory() {
+    let mut memory = memory_site();
+    memory.set_file("out/stale.html", "old");
+    let actions = vec![
+        BuildAction::BuildMultiplePages{
+            default_params: params("input: pages/page.html"),
+            on: vec![BuildMultiplePages{files: vec!["pages.yaml".to_owned()], params: vec![], mapping: params("output: \"out/{{slug}}.html\"")}],
+        },
+        BuildAction::CopyFiles{to: "out/static".to_owned(), from: "static".to_owned(), options: CopyOptions::default()},
+    ];
+    let pruned = build_and_prune(&actions, &BuildContext::default(), &setup_pipes(), &mut memory, &PruneOptions::new("out"));
+    assert_eq!(Ok(vec!["out/stale.html".to_owned()]), pruned);
+    assert_eq!(Ok("<main><p>two</p></main>"), memory.read("out/two.html"));
+    assert_eq!(
+        Ok(vec!["out/one.html".to_owned(), "out/static/css/app.css".to_owned(), "out/static/img/logo.png".to_owned(), "out/two.html".to_owned()]),
+        memory.list_files("out")
+    );
+}
+
+#[test]
+fn snapshot_and_dump_round_trip() {
+    let dir = temp_dir("memory", &[("src/sub/page.txt", "page {{name}}")]);
+    fs::write(dir.join("src/raw.bin"), [0u8, 0xff]).unwrap();
+    let mut memory = MemoryFs::snapshot(&dir.join("src").to_string_lossy()).unwrap();
+    assert_eq!(vec!["raw.bin", "sub/page.txt"], memory.filenames());
+    let actions = vec![BuildAction::BuildPage{output: "built.txt".to_owned(), input: "sub/page.txt".to_owned(), params: params("name: mem")}];
+    assert_eq!(Ok(()), run_actions(&actions, &BuildContext::default(), &setup_pipes(), &mut memory));
+    assert!(!dir.join("src/built.txt").exists());
+    memory.dump(&dir.join("dumped").to_string_lossy()).unwrap();
+    assert_eq!("page mem", fs::read_to_string(dir.join("dumped/built.txt")).unwrap());
+    assert_eq!(vec![0u8, 0xff], fs::read(dir.join("dumped/raw.bin")).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
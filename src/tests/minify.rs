@@ -0,0 +1,61 @@
+use crate::build::{BuildAction, BuildContext};
+use crate::io::ReadsFiles;
+use crate::memory::MemoryFs;
+use crate::minify::{MinifyOptions, minify_css, minify_html, minify_js};
+use crate::tests::common::setup_pipes;
+use yaml_rust2::yaml::{Hash, Yaml};
+
+#[test]
+fn html_collapses_text_but_not_pre_or_attributes() {
+    let html = "<div  class=\"a  b\">\n  <!-- note -->\n  <p>one\n\n two</p>\n  <pre>  keep\n   this </pre><TEXTAREA> and\n this</TEXTAREA>\n</div>";
+    assert_eq!(
+        "<div  class=\"a  b\"> <p>one two</p> <pre>  keep\n   this </pre><TEXTAREA> and\n this</TEXTAREA> </div>",
+        minify_html(html, &MinifyOptions{html: true, ..MinifyOptions::default()})
+    );
+    assert_eq!("<!--[if IE]>old<![endif]--> x", minify_html("<!--[if IE]>old<![endif]-->  x", &MinifyOptions::default()));
+}
+
+#[test]
+fn html_minifies_inline_styles_and_scripts() {
+    let html = "<style>\n  a { color: red; }\n</style><script>\n  let a = 1;\n</script><script type=\"text/template\">\n  {{x}}  </script>";
+    assert_eq!(
+        "<style>a{color:red}</style><script>let a=1;</script><script type=\"text/template\">\n  {{x}}  </script>",
+        minify_html(html, &MinifyOptions::production())
+    );
+}
+
+#[test]
+fn css_keeps_strings_and_selector_spaces() {
+    assert_eq!(
+        "a :hover,b>c{content:\"  x  ;\";margin:0 auto}/*! keep */ @media (max-width: 10px){a{color:red}}",
+        minify_css("/* gone */\na :hover, b>c {\n  content: \"  x  ;\";\n  margin: 0  auto;\n}\n/*! keep */\n@media (max-width: 10px) {\n  a { color: red; }\n}\n")
+    );
+}
+
+#[test]
+fn js_keeps_line_breaks_strings_and_regexes() {
+    let js = "// header\nconst a = \"x  // y\"  ;\nlet b = a.replace(/\\/\\/ +/g, '')   /* gone */\nlet c = `one\n   two`\nreturn a + +b - -c\n\n\n";
+    assert_eq!(
+        "const a=\"x  // y\";\nlet b=a.replace(/\\/\\/ +/g,'')\nlet c=`one\n   two`\nreturn a+ +b- -c",
+        minify_js(js)
+    );
+}
+
+fn build(ctx: &BuildContext, output: &str) -> String {
+    let mut io = MemoryFs::from_files([("page.html", "<p>\n  {{title}}\n</p>\n<!-- c -->\n")]);
+    let mut params = Hash::new();
+    params.insert(Yaml::String("title".to_owned()), Yaml::String("hi".to_owned()));
+    let action = BuildAction::BuildPage{output: output.to_owned(), input: "page.html".to_owned(), params};
+    action.run_in(ctx, &setup_pipes(), &mut io).unwrap();
+    io.read(output).unwrap().to_owned()
+}
+
+#[test]
+fn pages_are_minified_by_extension_in_production() {
+    let plain = "<p>\n  hi\n</p>\n<!-- c -->\n";
+    assert_eq!(plain, build(&BuildContext::default(), "out.html"));
+    assert_eq!("<p> hi </p> ", build(&BuildContext::default().production(), "out.html"));
+    assert_eq!(plain, build(&BuildContext::default().production(), "out.txt"));
+    let css_only = BuildContext{minify: MinifyOptions{css: true, ..MinifyOptions::default()}, ..BuildContext::default()};
+    assert_eq!(plain, build(&css_only, "out.html"));
+}
@@ -1,3 +1,14 @@
 pub mod common;
 pub mod parser;
 pub mod build;
+pub mod copy;
+pub mod yaml;
+pub mod watch;
+pub mod serve;
+pub mod parallel;
+pub mod plan;
+pub mod prune;
+pub mod io;
+pub mod memory;
+pub mod minify;
+pub mod transform;
@@ -0,0 +1,111 @@
+use crate::build::{BuildAction, BuildContext, BuildError, BuildMultiplePages};
+use crate::copy::CopyOptions;
+use crate::io::{FileCache, FileError, SharedFileCache};
+use crate::parallel::{map_parallel, run_actions_parallel};
+use crate::template::TemplateError;
+use crate::tests::common::{params, setup_pipes, temp_dir};
+use yaml_rust2::yaml::Hash;
+use std::fs;
+use std::path::PathBuf;
+
+fn temp_site(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let mut files = files.to_vec();
+    files.extend([("page.txt", "page {{num}} {% file @ inc %}"), ("inc.txt", "included")]);
+    temp_dir(&format!("parallel-{}", name), &files)
+}
+
+fn many_pages(dir: &str, count: usize) -> BuildAction {
+    let entries: Vec<Hash> = (0..count).map(|ii| params(&format!("num: {}", ii))).collect();
+    BuildAction::BuildMultiplePages{
+        default_params: params(&format!("input: {0}/page.txt\ninc: {0}/inc.txt", dir)),
+        on: vec![BuildMultiplePages{files: vec![], params: entries, mapping: params(&format!("output: \"{}/out{{{{num}}}}.txt\"", dir))}],
+    }
+}
+
+#[test]
+fn map_parallel_keeps_order() {
+    let io = SharedFileCache::new(FileCache::new());
+    let items: Vec<usize> = (0..100).collect();
+    assert_eq!(items.iter().map(|ii| ii * 2).collect::<Vec<usize>>(), map_parallel(&items, 4, &io, |ii, _| ii * 2));
+}
+
+#[test]
+fn parallel_pages_match_serial_pages() {
+    let dir = temp_site("pages", &[]);
+    let root = dir.to_string_lossy().into_owned();
+    let mut io = SharedFileCache::new(FileCache::new());
+    assert_eq!(Ok(()), many_pages(&root, 50).run_parallel(&BuildContext::default(), &setup_pipes(), &mut io, 4));
+    for ii in 0..50 {
+        assert_eq!(format!("page {} included", ii), fs::read_to_string(dir.join(format!("out{}.txt", ii))).unwrap());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parallel_pages_report_errors_in_order() {
+    let dir = temp_site("errors", &[]);
+    let root = dir.to_string_lossy().into_owned();
+    let mut io = SharedFileCache::new(FileCache::new());
+    let action = BuildAction::BuildMultiplePages{
+        default_params: params(&format!("input: {}/page.txt", root)),
+        on: vec![BuildMultiplePages{
+            files: vec![],
+            params: (0..20).map(|ii| params(&format!("num: {0}\ninc: missing{0}.txt", ii))).collect(),
+            mapping: params(&format!("output: \"{}/out{{{{num}}}}.txt\"", root)),
+        }],
+    };
+    let missing = BuildError::Sourced(Box::new(BuildError::TemplateError(
+        TemplateError::FileError(FileError::FileNotFound("missing0.txt".to_string()))
+    )));
+    for _ in 0..5 {
+        let ctx = BuildContext{fail_fast: true, ..BuildContext::default()};
+        assert_eq!(Err(&missing), action.run_parallel(&ctx, &setup_pipes(), &mut io, 4).as_ref());
+    }
+    match action.run_parallel(&BuildContext::default(), &setup_pipes(), &mut io, 4) {
+        Err(BuildError::Many(errors)) => assert_eq!((0..20).map(Some).collect::<Vec<Option<usize>>>(), errors.iter().map(|ee| ee.index).collect::<Vec<Option<usize>>>()),
+        other => panic!("expected many errors, got {:?}", other),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn independent_actions_in_parallel() {
+    let dir = temp_site("actions", &[]);
+    let root = dir.to_string_lossy().into_owned();
+    let mut io = SharedFileCache::new(FileCache::new());
+    let actions: Vec<BuildAction> = (0..8).map(|ii| BuildAction::BuildPage{
+        output: format!("{}/single{}.txt", root, ii),
+        input: format!("{}/page.txt", root),
+        params: params(&format!("num: {}\ninc: {}/inc.txt", ii, root)),
+    }).collect();
+    assert_eq!(Ok(()), run_actions_parallel(&actions, &BuildContext::default(), &setup_pipes(), &mut io, 3));
+    assert_eq!("page 7 included", fs::read_to_string(dir.join("single7.txt")).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parallel_actions_keep_their_order() {
+    let dir = temp_site("ordered", &[("theme/a.css", "theme a"), ("theme/b.css", "theme b"), ("site/a.css", "site a")]);
+    let root = dir.to_string_lossy().into_owned();
+    let copy = |from: &str| BuildAction::CopyFiles{to: format!("{}/out", root), from: format!("{}/{}", root, from), options: CopyOptions::default()};
+    let actions = vec![
+        copy("theme"),
+        copy("site"),
+        BuildAction::BuildPage{
+            output: format!("{}/single.txt", root),
+            input: format!("{}/page.txt", root),
+            params: params(&format!("num: single\ninc: {}/out/a.css", root)),
+        },
+        many_pages(&root, 10),
+    ];
+    for _ in 0..5 {
+        let _ = fs::remove_dir_all(dir.join("out"));
+        let mut io = SharedFileCache::new(FileCache::new());
+        assert_eq!(Ok(()), run_actions_parallel(&actions, &BuildContext::default(), &setup_pipes(), &mut io, 4));
+        assert_eq!("site a", fs::read_to_string(dir.join("out/a.css")).unwrap());
+        assert_eq!("theme b", fs::read_to_string(dir.join("out/b.css")).unwrap());
+        assert_eq!("page single site a", fs::read_to_string(dir.join("single.txt")).unwrap());
+        assert_eq!("page 9 included", fs::read_to_string(dir.join("out9.txt")).unwrap());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,11 +1,11 @@
-use crate::template::{render, TemplateError};
+use crate::template::{render, render_with, render_to, render_to_io, RenderOptions, SourcePaths, TemplateError};
 use crate::pipes::{PipeMap, PipeDefinition, new_pipe_map};
 use crate::parsers::{parse_template_string};
 use crate::io::{ReadsFiles, FileError};
-use crate::yaml::{load_yaml, YamlValue, YamlFileError};
+use crate::yaml::{load_yaml, YamlValue, YamlFileError, CompoundFormat};
 use yaml_rust2::{yaml::{Hash, Yaml}, YamlLoader};
 use std::collections::HashMap;
-use crate::tests::common::{TestFileCache, setup_io, setup_pipes};
+use crate::tests::common::{TestFileCache, params, setup_io, setup_pipes};
 
 fn accept(
     input: &str,
@@ -143,3 +143,152 @@ fn replacement_with_pipe_template_3() {
 fn replacement_with_function_pipe_1() {
     accept("foo {{bar | testfn}} yay", "bar: {nah: yeah}", "foo bleh yay");
 }
+#[test]
+fn replacement_of_literal() {
+    accept("foo {{ \"a b.css\" }} {{\"bar\" | test1}} yay", "bar: test", "foo a b.css um2 bar yay");
+}
+#[test]
+fn for_loop_over_csv_file() {
+    accept("{% for it in-file people.csv %}{{it.name}}={{it.age}};{% endfor %}", "{}", "ann=30;bo, jr=4;");
+}
+#[test]
+fn for_loop_over_explicit_csv_file() {
+    accept("{% for it in-file-csv people.txt %}{{it.name}}{% endfor %}", "{}", "cy");
+}
+#[test]
+fn for_loop_over_explicit_csv_file_at() {
+    accept("{% for it in-file-at-csv loc %}{{it.age}}{% endfor %}", "loc: people.txt", "9");
+}
+#[test]
+fn for_loop_over_json_file() {
+    accept("{% for it in-file list.json %}{{it.name}} {% endfor %}", "{}", "j1 j2 ");
+}
+#[test]
+fn for_loop_over_yaml_stream() {
+    accept("{% for it in-file stream.yaml %}{{it.name}} {% endfor %}", "{}", "s1 s2 ");
+}
+#[test]
+fn for_loop_over_single_document_stream() {
+    accept("{% for it in-file single.yaml %}{{it.name}}{% endfor %}", "{}", "solo");
+}
+#[test]
+fn for_loop_over_empty_file() {
+    reject("{% for it in-file empty.yaml %}{% endfor %}", "{}", TemplateError::YamlFileError(YamlFileError::Empty));
+}
+#[test]
+fn replacement_of_booleans() {
+    accept("{{yes}} {{no}}", "yes: true\nno: false", "true false");
+}
+#[test]
+fn replacement_of_null() {
+    accept("[{{nothing}}]", "nothing: ~", "[]");
+}
+#[test]
+fn replacement_of_hash_as_json() {
+    accept("{{bar}}", "bar: {a: 1, b: [x, \"q\\\"\"], c: ~, d: 1.5}", "{\"a\":1,\"b\":[\"x\",\"q\\\"\"],\"c\":null,\"d\":1.5}");
+}
+
+fn render_compound(input: &str, yaml: &str, compound: CompoundFormat) -> Result<String, TemplateError> {
+    render_with(input, &params(yaml), &setup_pipes(), &RenderOptions{compound, ..RenderOptions::default()}, &mut setup_io())
+}
+#[test]
+fn replacement_of_hash_as_yaml() {
+    assert_eq!(Ok("a: 1\nb:\n  - x".to_owned()), render_compound("{{bar}}", "bar: {a: 1, b: [x]}", CompoundFormat::Yaml));
+}
+#[test]
+fn replacement_of_hash_when_strict() {
+    assert_eq!(
+        Err(TemplateError::NonScalarValue("bar.inner".to_owned())),
+        render_compound("{{bar.inner}}", "bar: {inner: {a: 1}}", CompoundFormat::Strict)
+    );
+}
+#[test]
+fn replacement_of_scalar_when_strict() {
+    assert_eq!(Ok("1 true".to_owned()), render_compound("{{bar.aa}} {{bar.bb}}", "bar: {aa: 1, bb: true}", CompoundFormat::Strict));
+}
+
+fn overlay_io() -> TestFileCache {
+    let mut io = setup_io();
+    io.set_file("proj/site/snips/head.txt", "site head");
+    io.set_file("proj/theme/snips/head.txt", "theme head");
+    io.set_file("proj/theme/snips/foot.txt", "theme foot");
+    io.set_file("proj/theme/about.txt", "theme about");
+    io.set_file("proj/site/items.yaml", "[s1, s2]");
+    io.set_file("proj/theme/items.yaml", "[t1]");
+    io
+}
+
+fn render_overlay(input: &str) -> Result<String, TemplateError> {
+    let options = RenderOptions{
+        sources: SourcePaths{
+            root: "proj/".to_owned(),
+            snippet_dir: "snips".to_owned(),
+            search: vec!["site".to_owned(), "theme".to_owned()],
+        },
+        ..RenderOptions::default()
+    };
+    render_with(input, &params("page: about.txt"), &setup_pipes(), &options, &mut overlay_io())
+}
+#[test]
+fn snippets_resolve_through_search_paths() {
+    assert_eq!(Ok("site head, theme foot".to_owned()), render_overlay("{% snippet head.txt %}, {% snippet foot.txt %}"));
+}
+#[test]
+fn files_resolve_through_search_paths() {
+    assert_eq!(Ok("theme about theme about".to_owned()), render_overlay("{% file about.txt %} {% file @ page %}"));
+    assert_eq!(
+        Err(TemplateError::FileError(FileError::FileNotFound("proj/site/missing.txt".to_owned()))),
+        render_overlay("{% file missing.txt %}")
+    );
+}
+#[test]
+fn in_file_resolves_through_search_paths() {
+    assert_eq!(Ok("s1s2".to_owned()), render_overlay("{% for it in-file items.yaml %}{{it}}{% endfor %}"));
+}
+#[test]
+fn default_source_paths_match_old_prefix() {
+    assert_eq!(vec!["resources/snippets/aaa.txt".to_owned()], SourcePaths::default().candidates("aaa.txt", true));
+    assert_eq!(vec!["aaa.txt".to_owned()], SourcePaths::default().candidates("aaa.txt", false));
+}
+
+#[test]
+fn render_to_streams_into_a_string() {
+    let mut out = "before ".to_owned();
+    let params = params("items: [aa, bb]");
+    let result = render_to(&mut out, "{% for it in items %}{{it}}{% sep %}, {% endfor %}", &params, &setup_pipes(), &RenderOptions::default(), &mut setup_io());
+    assert_eq!(Ok(()), result);
+    assert_eq!("before aa, bb", out);
+}
+#[test]
+fn render_to_io_streams_bytes() {
+    let mut out: Vec<u8> = vec![];
+    let params = params("bar: yes");
+    assert_eq!(Ok(()), render_to_io(&mut out, "{{bar}} {% file aaa.txt %}", &params, &setup_pipes(), &RenderOptions::default(), &mut setup_io()));
+    assert_eq!(b"yes apple".to_vec(), out);
+}
+struct FailingWriter;
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("disk full"))
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+#[test]
+fn render_to_io_reports_write_errors() {
+    let result = render_to_io(&mut FailingWriter, "text", &params("{}"), &setup_pipes(), &RenderOptions::default(), &mut setup_io());
+    assert_eq!(Err(TemplateError::WriteError("disk full".to_owned())), result);
+}
+#[test]
+fn nested_loops_see_outer_variables() {
+    accept(
+        "{% for row in rows %}{% for col in row.cols %}{{row.name}}{{col}}{{bar}} {% endfor %}{% endfor %}",
+        "bar: '!'\nrows: [{name: a, cols: [1, 2]}, {name: b, cols: [3]}]",
+        "a1! a2! b3! "
+    );
+}
+#[test]
+fn loop_variables_shadow_params() {
+    accept("{{it}} {% for it in items %}{{it}}{% endfor %} {{it}}", "it: outer\nitems: [in]", "outer in outer");
+}
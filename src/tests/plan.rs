@@ -0,0 +1,93 @@
+use crate::build::{BuildAction, BuildContext, BuildMultiplePages, ParamsSource, run_actions};
+use crate::incremental::BuildCache;
+use std::sync::Mutex;
+use crate::copy::CopyOptions;
+use crate::plan::{PlanStatus, PlannedCopy, RecordingFiles, plan_actions};
+use crate::io::ReadsFiles;
+use crate::tests::common::{params, setup_io, setup_pipes};
+use yaml_rust2::yaml::Hash;
+
+#[test]
+fn recording_files_dont_write() {
+    let mut io = setup_io();
+    let mut recording = RecordingFiles::new(&mut io);
+    recording.write("aaa.txt", "apple").unwrap();
+    recording.write("bbb.txt", "changed").unwrap();
+    recording.write("new.txt", "fresh").unwrap();
+    recording.copy_files("static", "out/static").unwrap();
+    assert_eq!(Ok("fresh"), recording.read("new.txt"));
+    let statuses: Vec<PlanStatus> = recording.writes().iter().map(|ww| ww.status).collect();
+    assert_eq!(vec![PlanStatus::Unchanged, PlanStatus::Changed, PlanStatus::New], statuses);
+    assert_eq!(vec![PlannedCopy{from: "static".to_owned(), to: "out/static".to_owned()}], recording.copies());
+    assert!(io.written.is_empty());
+}
+
+#[test]
+fn plan_names_inputs_and_sources() {
+    let mut io = setup_io();
+    let actions = vec![
+        BuildAction::BuildPage{output: "out.txt".to_owned(), input: "base01.txt".to_owned(), params: params("bar: page")},
+        BuildAction::BuildMultiplePages{
+            default_params: params("input: front01.txt"),
+            on: vec![BuildMultiplePages{
+                files: vec!["multiple01.yaml".to_owned()],
+                params: vec![],
+                mapping: params("output: \"out-{{name}}.txt\""),
+            }],
+        },
+        BuildAction::CopyFiles{to: "out/static".to_owned(), from: "static".to_owned(), options: CopyOptions::default()},
+    ];
+    let plan = plan_actions(&actions, &BuildContext::default(), &setup_pipes(), &mut io).unwrap();
+    assert!(io.written.is_empty());
+    assert_eq!(3, plan.writes.len());
+    assert_eq!(Some("base01.txt".to_owned()), plan.writes[0].input);
+    assert_eq!(Some(ParamsSource::Action(0)), plan.writes[0].source);
+    assert_eq!(Some(ParamsSource::File("multiple01.yaml".to_owned())), plan.writes[2].source);
+    assert_eq!(Some(1), plan.writes[2].index);
+    assert_eq!(
+        "new       out.txt <- base01.txt (action 0), 12 bytes\n\
+new       out-one.txt <- front01.txt (multiple01.yaml entry 0), 11 bytes\n\
+new       out-two.txt <- front01.txt (multiple01.yaml entry 1), 11 bytes\n\
+copy      out/static <- static\n",
+        plan.to_text()
+    );
+}
+
+#[test]
+fn plan_as_json() {
+    let mut io = setup_io();
+    let actions = vec![BuildAction::BuildPage{output: "aaa.txt".to_owned(), input: "bbb.txt".to_owned(), params: Hash::new()}];
+    let plan = plan_actions(&actions, &BuildContext::default(), &setup_pipes(), &mut io).unwrap();
+    assert_eq!(
+        "{\"writes\":[{\"output\":\"aaa.txt\",\"input\":\"bbb.txt\",\"source\":\"action 0\",\"index\":null,\"bytes\":6,\"status\":\"changed\"}],\"copies\":[],\"errors\":[]}",
+        plan.to_json()
+    );
+}
+
+#[test]
+fn plan_keeps_errors() {
+    let mut io = setup_io();
+    let actions = vec![BuildAction::BuildPage{output: "out.txt".to_owned(), input: "missing.txt".to_owned(), params: Hash::new()}];
+    let plan = plan_actions(&actions, &BuildContext::default(), &setup_pipes(), &mut io).unwrap();
+    assert!(plan.writes.is_empty());
+    assert_eq!(1, plan.errors.len());
+    assert!(plan.to_text().starts_with("1 error\n  action 0:\n"));
+}
+
+#[test]
+fn plans_leave_the_cache_alone() {
+    let mut io = setup_io();
+    let ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    let actions = vec![BuildAction::BuildPage{output: "out.txt".to_owned(), input: "base01.txt".to_owned(), params: params("bar: page")}];
+    let plan = plan_actions(&actions, &ctx, &setup_pipes(), &mut io).unwrap();
+    assert_eq!(PlanStatus::New, plan.writes[0].status);
+    assert!(ctx.cache.as_ref().unwrap().lock().unwrap().built.is_empty());
+    run_actions(&actions, &ctx, &setup_pipes(), &mut io).unwrap();
+    io.assert_written("out.txt", "foo page yay");
+    let plan = plan_actions(&actions, &ctx, &setup_pipes(), &mut io).unwrap();
+    assert_eq!(
+        "unchanged out.txt <- base01.txt (action 0), 12 bytes\n",
+        plan.to_text()
+    );
+    assert!(ctx.cache.as_ref().unwrap().lock().unwrap().skipped.is_empty());
+}
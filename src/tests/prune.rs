@@ -0,0 +1,89 @@
+use crate::build::{BuildAction, BuildContext, BuildError};
+use crate::copy::CopyOptions;
+use crate::io::ReadsFiles;
+use crate::prune::{PruneOptions, TrackingOutputs, build_and_prune, prune};
+use crate::tests::common::{setup_io, setup_pipes};
+use yaml_rust2::yaml::Hash;
+
+fn with_output_dir() -> crate::tests::common::TestFileCache {
+    let mut io = setup_io();
+    io.set_file("out/page.txt", "old page");
+    io.set_file("out/gone.txt", "removed page");
+    io.set_file("out/CNAME", "example.com");
+    io.set_file("out/.git/HEAD", "ref");
+    io.set_file("out/static/app.css", "body {}");
+    io.set_file("static/app.css", "body {}");
+    io
+}
+
+#[test]
+fn tracking_outputs_expands_copied_dirs() {
+    let mut io = with_output_dir();
+    let mut tracking = TrackingOutputs::new(&mut io);
+    tracking.write("out/page.txt", "new").unwrap();
+    tracking.copy_files("static", "out/static").unwrap();
+    tracking.copy_files("aaa.txt", "out/aaa.txt").unwrap();
+    assert_eq!(
+        vec!["out/page.txt".to_owned(), "out/static/app.css".to_owned(), "out/aaa.txt".to_owned()],
+        tracking.outputs()
+    );
+}
+
+#[test]
+fn prune_dry_run_lists_stale_files() {
+    let mut io = with_output_dir();
+    let options = PruneOptions{dry_run: true, ..PruneOptions::new("out")};
+    let stale = prune(&["out/page.txt".to_owned(), "./out/static/app.css".to_owned()], &options, &mut io);
+    assert_eq!(Ok(vec!["out/gone.txt".to_owned()]), stale);
+    assert_eq!(Ok("removed page"), io.read("out/gone.txt"));
+}
+
+#[test]
+fn prune_respects_protect_globs() {
+    let mut io = with_output_dir();
+    let options = PruneOptions{protect: vec!["static/*.css".to_owned()], ..PruneOptions::new("out/")};
+    let stale = prune(&["out/page.txt".to_owned()], &options, &mut io).unwrap();
+    assert_eq!(vec!["out/.git/HEAD", "out/CNAME", "out/gone.txt"], stale);
+    assert!(io.read("out/gone.txt").is_err());
+    assert_eq!(Ok("body {}"), io.read("out/static/app.css"));
+}
+
+#[test]
+fn build_and_prune_keeps_what_was_built() {
+    let mut io = with_output_dir();
+    let actions = vec![
+        BuildAction::BuildPage{output: "out/page.txt".to_owned(), input: "aaa.txt".to_owned(), params: Hash::new()},
+        BuildAction::CopyFiles{to: "out/static".to_owned(), from: "static".to_owned(), options: CopyOptions::default()},
+    ];
+    let pruned = build_and_prune(&actions, &BuildContext::default(), &setup_pipes(), &mut io, &PruneOptions::new("out"));
+    assert_eq!(Ok(vec!["out/gone.txt".to_owned()]), pruned);
+    assert!(io.read("out/CNAME").is_ok());
+}
+
+#[test]
+fn build_and_prune_skips_pruning_after_errors() {
+    let mut io = with_output_dir();
+    let actions = vec![BuildAction::BuildPage{output: "out/page.txt".to_owned(), input: "missing.txt".to_owned(), params: Hash::new()}];
+    assert!(build_and_prune(&actions, &BuildContext::default(), &setup_pipes(), &mut io, &PruneOptions::new("out")).is_err());
+    assert!(io.read("out/gone.txt").is_ok());
+}
+
+#[test]
+fn prune_matches_absolute_outputs_against_a_relative_root() {
+    let mut io = with_output_dir();
+    let cwd = std::env::current_dir().unwrap().to_string_lossy().replace('\\', "/");
+    let outputs = vec![format!("{}/out/page.txt", cwd), format!("{}/out/static/../static/app.css", cwd)];
+    let options = PruneOptions{dry_run: true, ..PruneOptions::new("out")};
+    assert_eq!(Ok(vec!["out/gone.txt".to_owned()]), prune(&outputs, &options, &mut io));
+}
+
+#[test]
+fn prune_rejects_bad_protect_globs_and_keeps_stars_in_one_dir() {
+    let mut io = with_output_dir();
+    let options = PruneOptions{protect: vec!["[".to_owned()], ..PruneOptions::new("out")};
+    assert!(matches!(prune(&[], &options, &mut io), Err(BuildError::BadGlob(..))));
+    assert!(matches!(build_and_prune(&[], &BuildContext::default(), &setup_pipes(), &mut io, &options), Err(BuildError::BadGlob(..))));
+    assert_eq!(Ok("removed page"), io.read("out/gone.txt"));
+    let options = PruneOptions{protect: vec!["*.css".to_owned(), "*.txt".to_owned()], dry_run: true, ..PruneOptions::new("out")};
+    assert_eq!(Ok(vec!["out/.git/HEAD".to_owned(), "out/CNAME".to_owned(), "out/static/app.css".to_owned()]), prune(&[], &options, &mut io));
+}
@@ -0,0 +1,88 @@
+use crate::serve::{ServeOptions, ServeState, handle_request, inject_live_reload, resolve_path, start_server};
+use crate::tests::common::temp_dir;
+use crate::watch::WatchOptions;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+fn temp_site(name: &str) -> PathBuf {
+    temp_dir(&format!("serve-{}", name), &[
+        ("index.html", "<html><body>home</body></html>"),
+        ("sub/index.html", "sub"),
+        ("style.css", "body{}"),
+    ])
+}
+
+#[test]
+fn live_reload_goes_before_body_end() {
+    let injected = inject_live_reload("<body>hi</body>");
+    assert!(injected.starts_with("<body>hi<script>"));
+    assert!(injected.ends_with("</script></body>"));
+    assert!(inject_live_reload("hi").starts_with("hi<script>"));
+}
+
+#[test]
+fn paths_cant_leave_the_root() {
+    let dir = temp_site("paths");
+    assert_eq!(None, resolve_path(&dir, "/../secret"));
+    assert_eq!(None, resolve_path(&dir, "/sub/../../secret"));
+    assert_eq!(Some(dir.join("index.html")), resolve_path(&dir, "/"));
+    assert_eq!(Some(dir.join("sub/index.html")), resolve_path(&dir, "/sub?x=1"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn paths_are_percent_decoded() {
+    let dir = temp_site("decoded");
+    assert_eq!(Some(dir.join("my page.html")), resolve_path(&dir, "/my%20page.html"));
+    assert_eq!(Some(dir.join("caf\u{e9}/index.html")), resolve_path(&dir, "/caf%C3%A9/index.html?q=%20"));
+    assert_eq!(Some(dir.join("100%.html")), resolve_path(&dir, "/100%.html"));
+    assert_eq!(None, resolve_path(&dir, "/%2e%2e/secret"));
+    assert_eq!(None, resolve_path(&dir, "/sub%2F..%2F..%2Fsecret"));
+    assert_eq!(None, resolve_path(&dir, "/%FF.html"));
+    assert_eq!(None, resolve_path(&dir, "/a%00.html"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn serves_files_with_live_reload() {
+    let dir = temp_site("files");
+    let state = ServeState{version: 3, errors: vec![]};
+    let page = handle_request("GET", "/", &dir, &state);
+    assert_eq!(200, page.status);
+    assert!(String::from_utf8(page.body).unwrap().contains("__livereload"));
+    let css = handle_request("GET", "/style.css", &dir, &state);
+    assert_eq!(("text/css; charset=utf-8", b"body{}".to_vec()), (css.content_type, css.body));
+    assert_eq!(404, handle_request("GET", "/nope.html", &dir, &state).status);
+    assert_eq!(b"3".to_vec(), handle_request("GET", "/__livereload", &dir, &state).body);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn build_errors_replace_pages() {
+    let dir = temp_site("errors");
+    let state = ServeState{version: 1, errors: vec!["action 0: <oops>".to_string()]};
+    let page = handle_request("GET", "/sub/", &dir, &state);
+    assert_eq!(500, page.status);
+    let body = String::from_utf8(page.body).unwrap();
+    assert!(body.contains("&lt;oops&gt;"));
+    assert!(body.contains("__livereload"));
+    assert_eq!(200, handle_request("GET", "/style.css", &dir, &state).status);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn serves_over_http() {
+    let dir = temp_site("http");
+    let options = ServeOptions{output_dir: dir.to_string_lossy().into_owned(), port: 0, watch: WatchOptions::default()};
+    let listener = start_server(&options, Arc::new(Mutex::new(ServeState::default()))).unwrap();
+    let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    stream.write_all(b"GET /style.css HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nbody{}"));
+    fs::remove_dir_all(&dir).unwrap();
+}
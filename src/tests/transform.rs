@@ -0,0 +1,108 @@
+use crate::build::{BuildAction, BuildContext, BuildError, BuildMultiplePages, run_actions};
+use crate::incremental::BuildCache;
+use crate::io::{FileError, ReadsFiles};
+use crate::memory::MemoryFs;
+use crate::tests::common::{memory_site, params, setup_pipes};
+use crate::transform::{OutputTransforms, Transformed};
+use crate::yaml::YamlValue;
+use yaml_rust2::yaml::Hash;
+use std::sync::Mutex;
+
+fn actions() -> Vec<BuildAction> {
+    vec![
+        BuildAction::BuildPage{output: "out/feed.xml".to_owned(), input: "feed.xml".to_owned(), params: params("title: feed")},
+        BuildAction::BuildMultiplePages{
+            default_params: params("input: page.html"),
+            on: vec![BuildMultiplePages{
+                files: vec!["posts.yaml".to_owned()],
+                params: vec![],
+                mapping: params("output: \"out/posts/{{slug}}.html\""),
+            }],
+        },
+    ]
+}
+
+#[test]
+fn transforms_rewrite_matching_outputs() {
+    let transforms = OutputTransforms::new()
+        .on_extension("html", |_, rendered, _| Transformed::Rewrite(rendered.replace("</body>", "<script>track()</script></body>")))
+        .unwrap()
+        .on_glob("out/posts/*", |output, rendered, _| Transformed::Rewrite(format!("<!-- {} -->{}", output, rendered)))
+        .unwrap();
+    let ctx = BuildContext{transforms, ..BuildContext::default()};
+    let mut io = memory_site();
+    run_actions(&actions(), &ctx, &setup_pipes(), &mut io).unwrap();
+    assert_eq!(Ok("<feed>feed</feed>"), io.read("out/feed.xml"));
+    assert_eq!(Ok("<!-- out/posts/one.html --><body>one<script>track()</script></body>"), io.read("out/posts/one.html"));
+}
+
+#[test]
+fn transforms_can_veto_writes_from_params() {
+    let transforms = OutputTransforms::new()
+        .on_glob("*.{html,xml}", |_, _, params| match params.get(&YamlValue::String("draft".to_owned())) {
+            Some(YamlValue::Boolean(true)) => Transformed::Veto,
+            _ => Transformed::Keep,
+        })
+        .unwrap();
+    let ctx = BuildContext{transforms, ..BuildContext::default()};
+    let mut io = memory_site();
+    run_actions(&actions(), &ctx, &setup_pipes(), &mut io).unwrap();
+    assert_eq!(Ok("<body>one</body>"), io.read("out/posts/one.html"));
+    assert_eq!(Err(FileError::FileNotFound("out/posts/two.html".to_owned())), io.read("out/posts/two.html"));
+    assert!(io.read("out/feed.xml").is_ok());
+}
+
+#[test]
+fn transforms_reject_bad_globs() {
+    assert!(matches!(
+        OutputTransforms::new().on_glob("[", |_, _, _| Transformed::Keep),
+        Err(BuildError::BadGlob(..))
+    ));
+}
+
+#[test]
+fn transforms_see_front_matter() {
+    let mut io = MemoryFs::from_files([
+        ("draft.html", "---\ndraft: true\nlayout: layout.html\n---\nwip"),
+        ("post.html", "---\nlayout: layout.html\n---\ndone"),
+        ("layout.html", "---\nsection: blog\n---\n<main>{{content}}</main>"),
+    ]);
+    let transforms = OutputTransforms::new()
+        .on_extension(".html", |_, rendered, params| {
+            let get = |key: &str| params.get(&YamlValue::String(key.to_owned())).cloned();
+            match (get("draft"), get("section"), get("content")) {
+                (Some(YamlValue::Boolean(true)), _, _) => Transformed::Veto,
+                (None, Some(YamlValue::String(section)), None) => Transformed::Rewrite(format!("{} {}", section, rendered)),
+                _ => Transformed::Keep,
+            }
+        })
+        .unwrap();
+    let ctx = BuildContext{transforms, ..BuildContext::default()};
+    let actions = vec![
+        BuildAction::BuildPage{output: "out/draft.html".to_owned(), input: "draft.html".to_owned(), params: Hash::new()},
+        BuildAction::BuildPage{output: "out/post.html".to_owned(), input: "post.html".to_owned(), params: Hash::new()},
+    ];
+    run_actions(&actions, &ctx, &setup_pipes(), &mut io).unwrap();
+    assert!(io.read("out/draft.html").is_err());
+    assert_eq!(Ok("blog <main>done</main>"), io.read("out/post.html"));
+}
+
+#[test]
+fn cached_pages_follow_changed_transforms() {
+    let mut io = MemoryFs::from_files([("page.html", "<body>{{title}}</body>")]);
+    let page = vec![BuildAction::BuildPage{output: "out/page.html".to_owned(), input: "page.html".to_owned(), params: params("title: hi")}];
+    let shout = |version: &str, suffix: &'static str| OutputTransforms::new()
+        .on_extension("html", move |_, rendered, _| Transformed::Rewrite(format!("{}{}", rendered, suffix)))
+        .unwrap()
+        .version(version);
+    let mut ctx = BuildContext{cache: Some(Mutex::new(BuildCache::new())), ..BuildContext::default()};
+    run_actions(&page, &ctx, &setup_pipes(), &mut io).unwrap();
+    for (transforms, expected) in [(shout("1", "!"), "<body>hi</body>!"), (shout("1", "?"), "<body>hi</body>!"), (shout("2", "?"), "<body>hi</body>?")] {
+        ctx.transforms = transforms;
+        run_actions(&page, &ctx, &setup_pipes(), &mut io).unwrap();
+        assert_eq!(Ok(expected), io.read("out/page.html"));
+    }
+    ctx.transforms = OutputTransforms::new();
+    run_actions(&page, &ctx, &setup_pipes(), &mut io).unwrap();
+    assert_eq!(Ok("<body>hi</body>"), io.read("out/page.html"));
+}
@@ -0,0 +1,104 @@
+use crate::build::{BuildAction, BuildContext, BuildMultiplePages};
+use crate::assets::{Fingerprint, fingerprinted_name};
+use crate::copy::CopyOptions;
+use crate::io::ReadsFiles;
+use crate::memory::MemoryFs;
+use crate::watch::{Watcher, WatchSession};
+use crate::tests::common::{params, setup_io, setup_pipes, temp_dir};
+use std::fs;
+
+fn actions() -> Vec<BuildAction> {
+    vec![
+        BuildAction::BuildPage{output: "a.txt".to_string(), input: "include01.txt".to_string(), params: params("{}")},
+        BuildAction::BuildPage{output: "b.txt".to_string(), input: "base01.txt".to_string(), params: params("bar: b")},
+        BuildAction::BuildMultiplePages{
+            default_params: params("input: base01.txt\noutput: c.txt"),
+            on: vec![BuildMultiplePages{files: vec!["entry1.yaml".to_string()], params: vec![], mapping: params("{}")}],
+        },
+    ]
+}
+
+#[test]
+fn watch_session_reruns_only_affected_actions() {
+    let mut io = setup_io();
+    let actions = actions();
+    let mut session = WatchSession::new(&actions);
+    let errors = session.run_all(&BuildContext::default(), &setup_pipes(), &mut io);
+    assert_eq!(1, errors.len());
+    assert_eq!(vec![2], session.affected(&[]));
+    assert_eq!(vec![0, 2], session.affected(&["aaa.txt".to_string()]));
+    assert_eq!(vec![1, 2], session.affected(&["./base01.txt".to_string()]));
+    assert_eq!(vec![2], session.affected(&["zzz.txt".to_string()]));
+}
+
+#[test]
+fn watch_session_tracks_directory_listings() {
+    let mut io = setup_io();
+    let actions = vec![BuildAction::BuildPage{output: "a.txt".to_string(), input: "site01.txt".to_string(), params: params("{}")}];
+    let mut session = WatchSession::new(&actions);
+    let ctx = BuildContext::with_data_dir("data", &mut io).unwrap();
+    assert!(session.run_all(&ctx, &setup_pipes(), &mut io).is_empty());
+    assert_eq!(vec![0], session.affected(&["site01.txt".to_string()]));
+    assert!(session.affected(&["data/nav.yaml".to_string()]).is_empty());
+}
+
+#[test]
+fn watch_session_reruns_copies_when_their_sources_change() {
+    let mut io = setup_io();
+    let actions = vec![
+        BuildAction::CopyFiles{to: "out/static".to_string(), from: "static".to_string(), options: CopyOptions::default()},
+        BuildAction::CopyFiles{to: "out/aaa.txt".to_string(), from: "./aaa.txt".to_string(), options: CopyOptions::default()},
+    ];
+    let mut session = WatchSession::new(&actions);
+    assert!(session.run_all(&BuildContext::default(), &setup_pipes(), &mut io).is_empty());
+    assert_eq!(vec![0], session.affected(&["static/a.css".to_string()]));
+    assert_eq!(vec![1], session.affected(&["aaa.txt".to_string()]));
+    assert!(session.affected(&["out/static/a.css".to_string()]).is_empty());
+}
+
+#[test]
+fn watcher_notices_changes() {
+    let dir = temp_dir("watch", &[("one.txt", "one"), ("sub/two.txt", "two")]);
+    let root = dir.to_string_lossy().into_owned();
+    let mut watcher = Watcher::new(std::slice::from_ref(&root));
+    assert!(watcher.changes().is_empty());
+    fs::write(dir.join("sub/two.txt"), "two, but longer").unwrap();
+    fs::write(dir.join("three.txt"), "three").unwrap();
+    fs::remove_file(dir.join("one.txt")).unwrap();
+    let mut expected = vec![format!("{}/one.txt", root), format!("{}/sub/two.txt", root), format!("{}/three.txt", root)];
+    expected.sort();
+    assert_eq!(expected, watcher.changes());
+    assert!(watcher.changes().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn watch_session_refreshes_fingerprinted_assets() {
+    let mut io = MemoryFs::from_files([
+        ("static/app.css", "body {}"),
+        ("static/old.css", "old"),
+        ("page.html", "{{ \"app.css\" | asset }}"),
+        ("other.html", "other"),
+    ]);
+    let ctx = BuildContext::default();
+    let mut pipes = setup_pipes();
+    pipes.insert("asset".to_owned(), ctx.render.assets.pipe());
+    let actions = vec![
+        BuildAction::CopyFiles{
+            to: "out".to_owned(),
+            from: "static".to_owned(),
+            options: CopyOptions{fingerprint: Some(Fingerprint::default()), ..CopyOptions::default()},
+        },
+        BuildAction::BuildPage{output: "out/index.html".to_owned(), input: "page.html".to_owned(), params: params("{}")},
+        BuildAction::BuildPage{output: "out/other.html".to_owned(), input: "other.html".to_owned(), params: params("{}")},
+    ];
+    let mut session = WatchSession::new(&actions);
+    assert!(session.run_all(&ctx, &pipes, &mut io).is_empty());
+    assert_eq!(vec![0, 1], session.affected(&["static/app.css".to_string()]));
+    io.set_file("static/app.css", "body {color: red}");
+    io.remove("static/old.css").unwrap();
+    let which = session.affected(&["static/app.css".to_string(), "static/old.css".to_string()]);
+    assert!(session.run(&which, &ctx, &pipes, &mut io).is_empty());
+    assert_eq!(Ok(fingerprinted_name("app.css", b"body {color: red}").as_str()), io.read("out/index.html"));
+    assert_eq!(None, ctx.render.assets.get("old.css"));
+}
@@ -0,0 +1,107 @@
+use crate::template::render;
+use crate::yaml::{load_yaml, split_front_matter, YamlValue, YamlFileError};
+use crate::tests::common::{params, setup_io, setup_pipes};
+use yaml_rust2::YamlLoader;
+
+fn loads(input: &str, expected: &str) {
+    let expected_doc = YamlLoader::load_from_str(expected).unwrap().remove(0);
+    assert_eq!(Ok(expected_doc), load_yaml(input));
+}
+
+#[test]
+fn alias_of_scalar() {
+    loads("a: &x hello\nb: *x", "a: hello\nb: hello");
+}
+
+#[test]
+fn alias_of_map() {
+    loads("a: &x {c: 1}\nb: *x", "a: {c: 1}\nb: {c: 1}");
+}
+
+#[test]
+fn alias_of_list() {
+    loads("a: &x [1, 2]\nb: *x", "a: [1, 2]\nb: [1, 2]");
+}
+
+#[test]
+fn merge_single_map() {
+    loads("d: &d {a: 1, b: 2}\ne:\n  <<: *d\n  c: 3", "d: {a: 1, b: 2}\ne: {a: 1, b: 2, c: 3}");
+}
+
+#[test]
+fn merge_explicit_keys_win() {
+    loads("d: &d {a: 1, b: 2}\ne:\n  b: 3\n  <<: *d", "d: {a: 1, b: 2}\ne: {b: 3, a: 1}");
+}
+
+#[test]
+fn merge_keeps_position_of_merge_key() {
+    loads("d: &d {b: 2}\ne:\n  a: 1\n  <<: *d\n  c: 3", "d: {b: 2}\ne: {a: 1, b: 2, c: 3}");
+}
+
+#[test]
+fn merge_list_earlier_maps_win() {
+    loads(
+        "x: &x {a: 1, b: 1}\ny: &y {b: 2, c: 2}\ne:\n  <<: [*x, *y]\n  d: 3",
+        "x: {a: 1, b: 1}\ny: {b: 2, c: 2}\ne: {a: 1, b: 1, c: 2, d: 3}"
+    );
+}
+
+#[test]
+fn merge_inline_map() {
+    loads("e:\n  <<: {a: 1}\n  b: 2", "e: {a: 1, b: 2}");
+}
+
+#[test]
+fn merge_of_a_map_that_merges() {
+    loads(
+        "base: &base {a: 1}\nmid: &mid\n  <<: *base\n  b: 2\ntop:\n  <<: *mid\n  c: 3",
+        "base: {a: 1}\nmid: {a: 1, b: 2}\ntop: {a: 1, b: 2, c: 3}"
+    );
+}
+
+#[test]
+fn merge_inside_list_entries() {
+    loads(
+        "- &defaults {colour: red, size: 1}\n- <<: *defaults\n  size: 2\n- <<: *defaults\n  colour: blue",
+        "- {colour: red, size: 1}\n- {colour: red, size: 2}\n- {size: 1, colour: blue}"
+    );
+}
+
+#[test]
+fn merge_in_every_document() {
+    loads(
+        "a: &a {x: 1}\n---\nb:\n  <<: {y: 2}",
+        "- a: {x: 1}\n- b: {y: 2}"
+    );
+}
+
+#[test]
+fn merge_of_scalar_is_an_error() {
+    assert_eq!(Err(YamlFileError::BadMergeKey), load_yaml("e:\n  <<: 3"));
+}
+
+#[test]
+fn merge_of_list_of_scalars_is_an_error() {
+    assert_eq!(Err(YamlFileError::BadMergeKey), load_yaml("e:\n  <<: [1, 2]"));
+}
+
+#[test]
+fn merge_in_front_matter() {
+    let (front, body) = split_front_matter("---\nd: &d {a: 1}\ne:\n  <<: *d\n---\nbody").unwrap();
+    assert_eq!(params("d: {a: 1}\ne: {a: 1}"), front);
+    assert_eq!("body", body);
+}
+
+#[test]
+fn merged_fields_are_visible_to_templates() {
+    let rendered = render(
+        "{% for it in-file merged.yaml %}{{it.name}}:{{it.colour}} {% endfor %}",
+        &params("{}"), &setup_pipes(), &mut setup_io()
+    );
+    assert_eq!(Ok("one:red two:blue ".to_owned()), rendered);
+}
+
+#[test]
+fn non_merge_values_untouched() {
+    assert_eq!(Ok(YamlValue::String("<<".to_owned())), load_yaml("'<<'"));
+}
@@ -0,0 +1,95 @@
+use crate::build::BuildError;
+use crate::copy::Globs;
+use crate::utils::hash_str;
+use crate::yaml::YamlMap;
+use std::fmt;
+use std::sync::Arc;
+
+//what a transform wants done with a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transformed {
+    Keep,
+    Rewrite(String),
+    //the page isn't written at all
+    Veto,
+}
+
+//given the output path, the rendered page and the page's params, front matter and layout front
+//matter included
+pub type TransformFn = dyn Fn(&str, &str, &YamlMap) -> Transformed + Send + Sync;
+
+//callbacks run on every page between rendering and writing, picked by globs on the output path. a
+//glob without a slash matches the file's name, so `*.html` covers every page. they run in the order
+//they were added, each seeing what the one before left, and the first veto stops the rest
+#[derive(Clone, Default)]
+pub struct OutputTransforms {
+    transforms: Vec<(String, Arc<Globs>, Arc<TransformFn>)>,
+    version: String,
+}
+
+impl OutputTransforms {
+    pub fn new() -> OutputTransforms {
+        OutputTransforms::default()
+    }
+
+    //`{a,b}` alternatives are allowed, as in CopyFiles
+    pub fn on_glob(
+        mut self,
+        glob: &str,
+        transform: impl Fn(&str, &str, &YamlMap) -> Transformed + Send + Sync + 'static
+    ) -> Result<OutputTransforms, BuildError> {
+        let globs = Globs::new(&[glob.to_owned()])?;
+        self.transforms.push((glob.to_owned(), Arc::new(globs), Arc::new(transform)));
+        Ok(self)
+    }
+
+    //`html` or `.html`
+    pub fn on_extension(
+        self,
+        ext: &str,
+        transform: impl Fn(&str, &str, &YamlMap) -> Transformed + Send + Sync + 'static
+    ) -> Result<OutputTransforms, BuildError> {
+        self.on_glob(&format!("*.{}", ext.trim_start_matches('.')), transform)
+    }
+
+    //cached pages are rebuilt when the globs or this change. a closure can't be compared, so bump it
+    //whenever what a transform does changes
+    pub fn version(self, version: &str) -> OutputTransforms {
+        OutputTransforms{version: version.to_owned(), ..self}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    //0 when there are none
+    pub fn hash(&self) -> u64 {
+        match self.is_empty() {
+            true => 0,
+            false => hash_str(&format!("{:?} {}", self, self.version)),
+        }
+    }
+
+    //the page as it should be written, or None if it was vetoed
+    pub fn apply(&self, output: &str, rendered: &str, params: &YamlMap) -> Option<String> {
+        let path = output.replace('\\', "/");
+        let mut current = rendered.to_owned();
+        for (_, globs, transform) in &self.transforms {
+            if !globs.matches(path.trim_start_matches("./")) {
+                continue;
+            }
+            match transform(output, &current, params) {
+                Transformed::Keep => (),
+                Transformed::Rewrite(rewritten) => current = rewritten,
+                Transformed::Veto => return None,
+            }
+        }
+        Some(current)
+    }
+}
+
+impl fmt::Debug for OutputTransforms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.transforms.iter().map(|(glob, _, _)| glob)).finish()
+    }
+}
@@ -83,3 +83,87 @@ pub fn map_mut<A, B>(
     for ii in list { entries.push(func(ii)) }
     entries
 }
+
+//64 bit fnv-1a. stable between runs and platforms, unlike the std hashers
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for bb in bytes {
+        hash ^= *bb as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn hash_str(strr: &str) -> u64 {
+    hash_bytes(strr.as_bytes())
+}
+
+//the one way paths are compared: backslashes become slashes, `.` and empty parts go and `..` takes
+//the part before it along, so `./a//x/../b/` is `a/b`. `..` at the start of a relative path is kept,
+//and above the top of an absolute one stays at the top
+pub fn clean_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." if parts.last().is_some_and(|last| *last != "..") => {
+                parts.pop();
+            },
+            ".." if absolute => (),
+            _ => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if absolute {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+//picked from the extension, for serving files and building data uris
+pub fn mime_type(filename: &str) -> &'static str {
+    let name = filename.rsplit('/').next().unwrap_or(filename);
+    let ext = match name.rfind('.') {
+        Some(ii) if ii > 0 => &name[ii + 1..],
+        _ => "",
+    };
+    match ext {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+//standard alphabet, padded
+pub fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |acc, (ii, bb)| acc | ((*bb as u32) << (16 - 8 * ii)));
+        for ii in 0..4 {
+            if ii <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[((bits >> (18 - 6 * ii)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
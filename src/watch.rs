@@ -0,0 +1,235 @@
+use crate::build::{BuildAction, BuildContext, BuildError};
+use crate::data::load_data_dir;
+use crate::incremental::TrackingFiles;
+use crate::io::{ReadsFiles, FileCache, WriteStats};
+use crate::pipes::PipeMap;
+use crate::utils::clean_path;
+use crate::yaml::{YamlValue, insert_value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub struct WatchOptions {
+    pub dirs: Vec<String>,
+    //when this changes the site data is reloaded and everything is rebuilt
+    pub data_dir: Option<String>,
+    pub poll_interval: Duration,
+    //changes keep being collected until there's been none for this long
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> WatchOptions {
+        WatchOptions{
+            dirs: vec![],
+            data_dir: None,
+            poll_interval: Duration::from_millis(200),
+            debounce: Duration::from_millis(100),
+        }
+    }
+}
+
+//notices changes by comparing the modification time and size of every file under the directories
+pub struct Watcher {
+    dirs: Vec<String>,
+    seen: HashMap<String, (SystemTime, u64)>,
+}
+
+fn scan_dir(dir: &Path, found: &mut HashMap<String, (SystemTime, u64)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() => scan_dir(&path, found),
+            Ok(meta) => {
+                let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                found.insert(clean_path(&path.to_string_lossy()), (modified, meta.len()));
+            },
+            Err(_) => (),
+        }
+    }
+}
+
+impl Watcher {
+    pub fn new(dirs: &[String]) -> Watcher {
+        let mut watcher = Watcher{dirs: dirs.to_owned(), seen: HashMap::new()};
+        watcher.seen = watcher.scan();
+        watcher
+    }
+
+    fn scan(&self) -> HashMap<String, (SystemTime, u64)> {
+        let mut found = HashMap::new();
+        for dir in &self.dirs {
+            scan_dir(Path::new(dir), &mut found);
+        }
+        found
+    }
+
+    //every file added, removed or modified since the last call, sorted
+    pub fn changes(&mut self) -> Vec<String> {
+        let now = self.scan();
+        let mut changed: Vec<String> = now.iter()
+            .filter(|(path, stamp)| self.seen.get(*path) != Some(stamp))
+            .map(|(path, _)| path.to_owned())
+            .collect();
+        changed.extend(self.seen.keys().filter(|path| !now.contains_key(*path)).cloned());
+        changed.sort();
+        self.seen = now;
+        changed
+    }
+
+    //blocks until something changes, then keeps collecting until things have gone quiet
+    pub fn wait_for_changes(&mut self, options: &WatchOptions) -> Vec<String> {
+        let mut changed = self.changes();
+        while changed.is_empty() {
+            thread::sleep(options.poll_interval);
+            changed = self.changes();
+        }
+        let mut quiet_since = Instant::now();
+        while quiet_since.elapsed() < options.debounce {
+            thread::sleep(options.poll_interval.min(options.debounce));
+            let more = self.changes();
+            if !more.is_empty() {
+                changed.extend(more);
+                quiet_since = Instant::now();
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+}
+
+//remembers what every action read the last time it ran, so a change only reruns the actions that
+//depend on it. actions that failed are always rerun
+pub struct WatchSession<'a> {
+    actions: &'a [BuildAction],
+    reads: Vec<Option<Vec<String>>>,
+}
+
+fn depends_on(reads: &[String], changed: &str) -> bool {
+    reads.iter().any(|read| {
+        let read = clean_path(read);
+        changed == read || changed.starts_with(&format!("{}/", read.trim_end_matches('/')))
+    })
+}
+
+impl<'a> WatchSession<'a> {
+    pub fn new(actions: &'a [BuildAction]) -> WatchSession<'a> {
+        WatchSession{actions, reads: vec![None; actions.len()]}
+    }
+
+    //indexes of the actions that need rerunning for these changes
+    pub fn affected(&self, changed: &[String]) -> Vec<usize> {
+        (0..self.actions.len()).filter(|ii| match &self.reads[*ii] {
+            None => true,
+            Some(reads) => changed.iter().any(|path| depends_on(reads, &clean_path(path))),
+        }).collect()
+    }
+
+    //runs the actions, returning the errors of any that failed. if a fingerprinted copy is among them
+    //the asset manifest is rebuilt from scratch by rerunning every fingerprinted copy, so assets that
+    //have gone don't linger. pages using an asset read its source, so they're already among them
+    pub fn run(
+        &mut self,
+        which: &[usize],
+        ctx: &BuildContext,
+        pipes: &PipeMap,
+        io: &mut impl ReadsFiles
+    ) -> Vec<(usize, BuildError)> {
+        let fingerprinted: Vec<usize> = (0..self.actions.len()).filter(|ii| matches!(
+            &self.actions[*ii],
+            BuildAction::CopyFiles{options, ..} if options.fingerprint.is_some()
+        )).collect();
+        let mut which = which.to_vec();
+        if which.iter().any(|ii| fingerprinted.contains(ii)) {
+            ctx.render.assets.clear();
+            which.extend(fingerprinted);
+            which.sort();
+            which.dedup();
+        }
+        let mut errors = vec![];
+        for ii in &which {
+            let mut tracking = TrackingFiles::new(io);
+            let result = self.actions[*ii].run_in(ctx, pipes, &mut tracking);
+            let reads = tracking.reads();
+            match result {
+                Ok(()) => self.reads[*ii] = Some(reads),
+                Err(ee) => {
+                    self.reads[*ii] = None;
+                    errors.push((*ii, ee));
+                }
+            }
+        }
+        errors
+    }
+
+    pub fn run_all(&mut self, ctx: &BuildContext, pipes: &PipeMap, io: &mut impl ReadsFiles) -> Vec<(usize, BuildError)> {
+        let all: Vec<usize> = (0..self.actions.len()).collect();
+        self.run(&all, ctx, pipes, io)
+    }
+}
+
+fn report(errors: &[(usize, BuildError)]) {
+    for (ii, ee) in errors {
+        eprintln!("action {} failed: {:?}", ii, ee);
+    }
+}
+
+//builds everything, then rebuilds whatever is affected each time files change. errors are printed
+//and the watch carries on
+pub fn watch(
+    actions: &[BuildAction],
+    ctx: &mut BuildContext,
+    pipes: &PipeMap,
+    io: &mut FileCache,
+    options: &WatchOptions
+) -> ! {
+    watch_with(actions, ctx, pipes, io, options, |_| ())
+}
+
+//watch, calling back with the errors after every build
+pub fn watch_with(
+    actions: &[BuildAction],
+    ctx: &mut BuildContext,
+    pipes: &PipeMap,
+    io: &mut FileCache,
+    options: &WatchOptions,
+    mut on_build: impl FnMut(&[(usize, BuildError)])
+) -> ! {
+    let mut watcher = Watcher::new(&options.dirs);
+    let mut session = WatchSession::new(actions);
+    let errors = session.run_all(ctx, pipes, io);
+    report(&errors);
+    on_build(&errors);
+    loop {
+        let changed = watcher.wait_for_changes(options);
+        for path in &changed {
+            io.invalidate(path);
+        }
+        println!("changed: {}", changed.join(", "));
+        let data_changed = match &options.data_dir {
+            Some(dir) => changed.iter().any(|path| depends_on(&[dir.to_owned()], path)),
+            None => false,
+        };
+        let which = if data_changed {
+            match load_data_dir(options.data_dir.as_ref().unwrap(), io) {
+                Ok(data) => insert_value(&mut ctx.site, "data", YamlValue::Hash(data)),
+                Err(ee) => eprintln!("couldn't reload site data: {:?}", ee),
+            }
+            (0..actions.len()).collect()
+        } else {
+            session.affected(&changed)
+        };
+        let errors = session.run(&which, ctx, pipes, io);
+        report(&errors);
+        println!("rebuilt {} of {} actions, {}", which.len(), actions.len(), io.stats);
+        io.stats = WriteStats::default();
+        on_build(&errors);
+    }
+}
@@ -16,18 +16,179 @@ pub type YamlValue = Yaml;
 pub enum YamlFileError {
     File(FileError),
     Yaml(ScanError),
+    Toml(String),
+    Csv(String),
+    Empty,
+    //a `<<` key whose value isn't a map or a list of maps
+    BadMergeKey,
 }
 
-pub fn load_yaml(strr: &str) -> Result<YamlValue, ScanError> {
-    let parsed = YamlLoader::load_from_str(strr);
-    match parsed {
-        Err(ee) => Err(ee),
-        Ok(ss) => Ok(ss[0].clone())
+//the formats data files can be loaded from. json is read with the yaml loader
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataFormat {
+    Yaml,
+    Json,
+    Toml,
+    Csv,
+}
+
+impl DataFormat {
+    pub fn from_extension(extension: &str) -> Option<DataFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Some(DataFormat::Yaml),
+            "json" => Some(DataFormat::Json),
+            "toml" => Some(DataFormat::Toml),
+            "csv" => Some(DataFormat::Csv),
+            _ => None,
+        }
+    }
+
+    //anything without a recognised extension is treated as yaml
+    pub fn from_filename(filename: &str) -> DataFormat {
+        match filename.rsplit_once('.') {
+            Some((_, ext)) => DataFormat::from_extension(ext).unwrap_or(DataFormat::Yaml),
+            None => DataFormat::Yaml,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrontMatterError {
+    Yaml(YamlFileError),
+    NotAHash,
+}
+
+//parses every document in the stream, resolving merge keys in each
+fn load_yaml_stream(strr: &str) -> Result<Vec<YamlValue>, YamlFileError> {
+    let parsed = YamlLoader::load_from_str(strr).map_err(YamlFileError::Yaml)?;
+    parsed.into_iter().map(resolve_merge_keys).collect()
+}
+
+//a single document comes back as is, several come back as an array of documents
+pub fn load_yaml(strr: &str) -> Result<YamlValue, YamlFileError> {
+    let mut parsed = load_yaml_stream(strr)?;
+    match parsed.len() {
+        0 => Err(YamlFileError::Empty),
+        1 => Ok(parsed.remove(0)),
+        _ => Ok(Yaml::Array(parsed)),
+    }
+}
+
+//yaml 1.1 merge keys. the maps under `<<` are spliced in where the key was, with the map's own keys
+//winning over merged ones and earlier maps in a list of merges winning over later ones
+pub fn resolve_merge_keys(value: YamlValue) -> Result<YamlValue, YamlFileError> {
+    match value {
+        Yaml::Array(aa) => Ok(Yaml::Array(
+            aa.into_iter().map(resolve_merge_keys).collect::<Result<Vec<Yaml>, YamlFileError>>()?
+        )),
+        Yaml::Hash(hh) => {
+            let merge_key = YamlString("<<".to_owned());
+            let mut resolved = new_yaml_map();
+            for (key, value) in hh.iter() {
+                if *key != merge_key {
+                    continue;
+                }
+                let sources = match resolve_merge_keys(value.to_owned())? {
+                    Yaml::Hash(source) => vec![source],
+                    Yaml::Array(sources) => sources.into_iter().map(|ii| match ii {
+                        Yaml::Hash(source) => Ok(source),
+                        _ => Err(YamlFileError::BadMergeKey),
+                    }).collect::<Result<Vec<Hash>, YamlFileError>>()?,
+                    _ => return Err(YamlFileError::BadMergeKey),
+                };
+                for source in sources {
+                    for (kk, vv) in source {
+                        if !hh.contains_key(&kk) && !resolved.contains_key(&kk) {
+                            resolved.insert(kk, vv);
+                        }
+                    }
+                }
+            }
+            let mut out = new_yaml_map();
+            for (key, value) in hh {
+                if key == merge_key {
+                    out.extend(std::mem::take(&mut resolved));
+                } else {
+                    out.insert(key, resolve_merge_keys(value)?);
+                }
+            }
+            Ok(Yaml::Hash(out))
+        },
+        other => Ok(other),
+    }
+}
+
+pub fn load_data(strr: &str, format: DataFormat) -> Result<YamlValue, YamlFileError> {
+    match format {
+        DataFormat::Yaml | DataFormat::Json => load_yaml(strr),
+        DataFormat::Toml => match strr.parse::<toml::Table>() {
+            Ok(table) => Ok(from_toml(toml::Value::Table(table))),
+            Err(ee) => Err(YamlFileError::Toml(ee.to_string())),
+        },
+        DataFormat::Csv => load_csv(strr),
+    }
+}
+
+fn from_toml(value: toml::Value) -> YamlValue {
+    match value {
+        toml::Value::String(ss) => Yaml::String(ss),
+        toml::Value::Integer(ii) => Yaml::Integer(ii),
+        toml::Value::Float(ff) => Yaml::Real(ff.to_string()),
+        toml::Value::Boolean(bb) => Yaml::Boolean(bb),
+        toml::Value::Datetime(dd) => Yaml::String(dd.to_string()),
+        toml::Value::Array(aa) => Yaml::Array(aa.into_iter().map(from_toml).collect()),
+        toml::Value::Table(tt) => Yaml::Hash(
+            tt.into_iter().map(|(kk, vv)| (Yaml::String(kk), from_toml(vv))).collect()
+        ),
     }
 }
 
+//every row becomes a hash keyed by the header row, with all values left as strings
+fn load_csv(strr: &str) -> Result<YamlValue, YamlFileError> {
+    let mut reader = csv::Reader::from_reader(strr.as_bytes());
+    let headers: Vec<Yaml> = reader.headers()
+        .map_err(|ee| YamlFileError::Csv(ee.to_string()))?
+        .iter()
+        .map(|hh| Yaml::String(hh.to_owned()))
+        .collect();
+    let mut rows: Vec<Yaml> = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|ee| YamlFileError::Csv(ee.to_string()))?;
+        let mut row = new_yaml_map();
+        for (header, field) in headers.iter().zip(record.iter()) {
+            row.insert(header.to_owned(), Yaml::String(field.to_owned()));
+        }
+        rows.push(Yaml::Hash(row));
+    }
+    Ok(Yaml::Array(rows))
+}
+
 pub fn new_yaml_map() -> Hash { Hash::new() }
 
+//splits a leading `---` delimited yaml block off the input, returning it as a map along with the
+//rest of the input. input without a (terminated) front matter block comes back untouched
+pub fn split_front_matter(strr: &str) -> Result<(YamlMap, &str), FrontMatterError> {
+    let after_open = match strr.strip_prefix("---\n").or_else(|| strr.strip_prefix("---\r\n")) {
+        None => return Ok((new_yaml_map(), strr)),
+        Some(ss) => ss,
+    };
+    let mut offset = 0;
+    for line in after_open.split_inclusive('\n') {
+        if line.trim_end() == "---" || line.trim_end() == "..." {
+            let front = &after_open[..offset];
+            let body = &after_open[offset + line.len()..];
+            return match load_yaml(front) {
+                Ok(Yaml::Hash(hh)) => Ok((hh, body)),
+                Ok(Yaml::Null) | Err(YamlFileError::Empty) => Ok((new_yaml_map(), body)),
+                Ok(_) => Err(FrontMatterError::NotAHash),
+                Err(ee) => Err(FrontMatterError::Yaml(ee)),
+            };
+        }
+        offset += line.len();
+    }
+    Ok((new_yaml_map(), strr))
+}
+
 pub fn lookup_yaml_map<'a, 'b>(key: &'a str, mapping: &'a YamlMap) -> Result<&'a Yaml, TemplateError> {
     let key_as_yaml = YamlString(key.to_owned());
     match mapping.get(&key_as_yaml) {
@@ -37,7 +198,11 @@ pub fn lookup_yaml_map<'a, 'b>(key: &'a str, mapping: &'a YamlMap) -> Result<&'a
 }
 
 pub fn lookup_value<'a, 'b>(value: &'a TemplateValue, params: &'a YamlMap) -> Result<&'a Yaml, TemplateError> {
-    let base = lookup_yaml_map(&value.base, params)?;
+    lookup_accesses(value, lookup_yaml_map(&value.base, params)?)
+}
+
+//follows the value's fields and indexes down from whatever its base turned out to be
+pub fn lookup_accesses<'a>(value: &'a TemplateValue, base: &'a Yaml) -> Result<&'a Yaml, TemplateError> {
     let mut path: String = value.base.to_owned();
     fold_m(base, &value.accesses, |current, aa|
         match aa {
@@ -71,20 +236,95 @@ pub fn lookup_value<'a, 'b>(value: &'a TemplateValue, params: &'a YamlMap) -> Re
     )
 }
 
-pub fn tostr(value: &Yaml) -> Result<String, TemplateError> {
-    let mut outstr = String::new();
+//how hashes and arrays are written out when they're interpolated into a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompoundFormat {
+    #[default]
+    Json,
+    //block yaml without the leading `---`
+    Yaml,
+    //refuse to write them at all, for catching values interpolated by accident
+    Strict,
+}
+
+pub fn tostr(value: &Yaml, compound: CompoundFormat) -> Result<String, TemplateError> {
     match value {
         Yaml::String(ss) => Ok(ss.clone()),
         Yaml::Real(ss) => Ok(ss.clone()),
         Yaml::Integer(ii) => Ok(format!("{}", ii)),
-        _ => match YamlEmitter::new(&mut outstr).dump(value) {
-            Ok(_) => {
-                println!("um {}", outstr);
-                Ok(outstr)
-            } ,
-            Err(ee) => Err(TemplateError::SerialisationError(ee.to_string()))
+        Yaml::Boolean(bb) => Ok(format!("{}", bb)),
+        Yaml::Null => Ok("".to_owned()),
+        Yaml::Array(..) | Yaml::Hash(..) => match compound {
+            CompoundFormat::Json => Ok(to_json(value)),
+            CompoundFormat::Yaml => to_yaml(value),
+            CompoundFormat::Strict => Err(TemplateError::NonScalarValue("".to_owned())),
+        },
+        Yaml::Alias(..) | Yaml::BadValue => Err(TemplateError::SerialisationError(format!("{:?}", value))),
+    }
+}
+
+fn to_yaml(value: &Yaml) -> Result<String, TemplateError> {
+    let mut outstr = String::new();
+    match YamlEmitter::new(&mut outstr).dump(value) {
+        Ok(_) => Ok(outstr.strip_prefix("---\n").unwrap_or(&outstr).to_owned()),
+        Err(ee) => Err(TemplateError::SerialisationError(ee.to_string()))
+    }
+}
+
+pub fn to_json(value: &Yaml) -> String {
+    let mut out = String::new();
+    write_json(value, &mut out);
+    out
+}
+
+fn write_json(value: &Yaml, out: &mut String) {
+    match value {
+        Yaml::String(ss) => write_json_string(ss, out),
+        Yaml::Integer(ii) => out.push_str(&ii.to_string()),
+        Yaml::Real(rr) => match value.as_f64() {
+            Some(ff) if ff.is_finite() => out.push_str(&ff.to_string()),
+            _ => write_json_string(rr, out),
+        },
+        Yaml::Boolean(bb) => out.push_str(&bb.to_string()),
+        Yaml::Array(aa) => {
+            out.push('[');
+            for (ii, item) in aa.iter().enumerate() {
+                if ii > 0 { out.push(','); }
+                write_json(item, out);
+            }
+            out.push(']');
+        },
+        Yaml::Hash(hh) => {
+            out.push('{');
+            for (ii, (key, item)) in hh.iter().enumerate() {
+                if ii > 0 { out.push(','); }
+                match key {
+                    Yaml::String(ss) => write_json_string(ss, out),
+                    _ => write_json_string(&tostr(key, CompoundFormat::Json).unwrap_or_default(), out),
+                }
+                out.push(':');
+                write_json(item, out);
+            }
+            out.push('}');
+        },
+        Yaml::Null | Yaml::Alias(..) | Yaml::BadValue => out.push_str("null"),
+    }
+}
+
+fn write_json_string(strr: &str, out: &mut String) {
+    out.push('"');
+    for cc in strr.chars() {
+        match cc {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            cc if (cc as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", cc as u32)),
+            cc => out.push(cc),
         }
     }
+    out.push('"');
 }
 
 pub fn to_iterable(value: &Yaml) -> Result<Vec<Yaml>, TemplateError> {